@@ -0,0 +1,209 @@
+use crate::grid::*;
+use crate::sim::Input;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+// every move the search tries from each position, hard drop is added at the end
+const MOVES: [Input; 10] = [
+    Input::Left,
+    Input::Right,
+    Input::Up,
+    Input::Down,
+    Input::Rotate(Axis::X, RotateDir::Clockwise),
+    Input::Rotate(Axis::X, RotateDir::CounterClockwise),
+    Input::Rotate(Axis::Y, RotateDir::Clockwise),
+    Input::Rotate(Axis::Y, RotateDir::CounterClockwise),
+    Input::Rotate(Axis::Z, RotateDir::Clockwise),
+    Input::Rotate(Axis::Z, RotateDir::CounterClockwise),
+];
+
+// how much each feature of the stack after a placement counts, higher is worse except cleared
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Weights {
+    // sum of column heights
+    pub height: f32,
+    // vacant cells with a block somewhere above them
+    pub holes: f32,
+    // height differences between neighboring columns
+    pub bumpiness: f32,
+    // planes cleared by the placement
+    pub cleared: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            height: 0.51,
+            holes: 0.36,
+            bumpiness: 0.18,
+            cleared: 0.76,
+        }
+    }
+}
+
+// a spot the falling piece can reach, and how to get it there
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+    // inputs from the piece's current position, ending in a hard drop
+    pub inputs: Vec<Input>,
+    // where the piece ends up
+    pub blocks: Vec<Block>,
+    pub score: f32,
+}
+
+// plays a grid by searching every placement of the current and held piece
+pub struct Bot {
+    pub weights: Weights,
+    // inputs still to send for the chosen placement
+    plan: VecDeque<Input>,
+    // tetris the plan was made for
+    planned: Option<usize>,
+}
+
+impl Bot {
+    pub fn new(weights: Weights) -> Self {
+        Self {
+            weights,
+            plan: VecDeque::new(),
+            planned: None,
+        }
+    }
+
+    // inputs for this frame, one at a time like a player pressing keys
+    pub fn next_inputs(&mut self, grid: &Grid) -> Vec<Input> {
+        if grid.end || !grid.tetris[grid.current].falling {
+            return vec![];
+        }
+        if self.planned != Some(grid.current) {
+            self.plan = self.best_placement(grid).map(|p| p.inputs.into()).unwrap_or_default();
+            self.planned = Some(grid.current);
+        }
+        self.plan.pop_front().into_iter().collect()
+    }
+
+    // best placement of the current piece, or of the held one if swapping scores better
+    pub fn best_placement(&self, grid: &Grid) -> Option<Placement> {
+        let mut best = self.search(grid, vec![]);
+        let mut held = grid.clone();
+        if held.hold_current() {
+            if let Some(p) = self.search(&held, vec![Input::Hold]) {
+                if best.as_ref().map_or(true, |b| p.score > b.score) {
+                    best = Some(p);
+                }
+            }
+        }
+        best
+    }
+
+    // breadth first search over positions reachable with moves and rotations, so each one comes with its shortest path
+    fn search(&self, grid: &Grid, prefix: Vec<Input>) -> Option<Placement> {
+        let i = grid.current;
+        // one copy to move the piece around in, every position is restored into it before trying moves
+        let mut scratch = grid.clone();
+        let mut best: Option<Placement> = None;
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(position_key(&grid.tetris[i].blocks));
+        queue.push_back((grid.tetris[i].clone(), prefix));
+
+        while let Some((t, path)) = queue.pop_front() {
+            scratch.set_tetris(i, t.clone());
+            let landing = scratch.landing_blocks(i);
+            let score = self.evaluate(&scratch, &landing);
+            if best.as_ref().map_or(true, |b| score > b.score) {
+                let mut inputs = path.clone();
+                inputs.push(Input::HardDrop);
+                best = Some(Placement {
+                    inputs,
+                    blocks: landing,
+                    score,
+                });
+            }
+
+            for m in MOVES.iter() {
+                scratch.set_tetris(i, t.clone());
+                let moved = match *m {
+                    Input::Left => scratch.shift_tetris(i, GridOffset::new(-1, 0, 0)),
+                    Input::Right => scratch.shift_tetris(i, GridOffset::new(1, 0, 0)),
+                    Input::Up => scratch.shift_tetris(i, GridOffset::new(0, 0, 1)),
+                    Input::Down => scratch.shift_tetris(i, GridOffset::new(0, 0, -1)),
+                    Input::Rotate(axis, dir) => scratch.rotate_tetris(i, axis, dir),
+                    _ => false,
+                };
+                if moved && seen.insert(position_key(&scratch.tetris[i].blocks)) {
+                    let mut p = path.clone();
+                    p.push(*m);
+                    queue.push_back((scratch.tetris[i].clone(), p));
+                }
+            }
+        }
+        best
+    }
+
+    // score the stack with the current piece landed at landing and any full planes removed
+    // removal always compacts naively, close enough for sticky and cascade gravity
+    fn evaluate(&self, grid: &Grid, landing: &[Block]) -> f32 {
+        let size = grid.size;
+        let current = GridBlock::Occupied(grid.current);
+        let mut filled: Vec<bool> = grid.grid.iter().map(|g| !g.is_vacant() && *g != current).collect();
+        for b in landing {
+            filled[size.coord_to_index(b.c)] = true;
+        }
+        let n = size.plane_len();
+        let kept: Vec<bool> = filled
+            .chunks(n)
+            .filter(|plane| !plane.iter().all(|f| *f))
+            .flatten()
+            .copied()
+            .collect();
+        let cleared = (filled.len() - kept.len()) / n;
+        let (height, holes, bumpiness) = stack_features(size, &kept);
+        self.weights.cleared * cleared as f32
+            - self.weights.height * height as f32
+            - self.weights.holes * holes as f32
+            - self.weights.bumpiness * bumpiness as f32
+    }
+}
+
+// order independent key for a set of blocks
+fn position_key(blocks: &[Block]) -> Vec<[i32; 3]> {
+    let mut key: Vec<[i32; 3]> = blocks.iter().map(|b| [b.c.x, b.c.y, b.c.z]).collect();
+    key.sort();
+    key
+}
+
+// aggregate column height, holes and bumpiness of a stack laid out like Grid::grid, planes above the end count as empty
+pub fn stack_features(size: GridSize, filled: &[bool]) -> (i32, i32, i32) {
+    let planes = (filled.len() / size.plane_len()) as i32;
+    let mut heights = vec![0; (size.x * size.z) as usize];
+    let mut holes = 0;
+    for x in 0..size.x {
+        for z in 0..size.z {
+            let mut top = 0;
+            for y in 0..planes {
+                if filled[size.xyz_to_index(x, y, z)] {
+                    top = y + 1;
+                }
+            }
+            for y in 0..top {
+                if !filled[size.xyz_to_index(x, y, z)] {
+                    holes += 1;
+                }
+            }
+            heights[(x * size.z + z) as usize] = top;
+        }
+    }
+    let h = |x: i32, z: i32| heights[(x * size.z + z) as usize];
+    let mut bumpiness = 0;
+    for x in 0..size.x {
+        for z in 0..size.z {
+            if x + 1 < size.x {
+                bumpiness += (h(x, z) - h(x + 1, z)).abs();
+            }
+            if z + 1 < size.z {
+                bumpiness += (h(x, z) - h(x, z + 1)).abs();
+            }
+        }
+    }
+    (heights.iter().sum(), holes, bumpiness)
+}
@@ -7,12 +7,53 @@ use real3d::{
 use std::ops::Add;
 use winit;
 use serde_json;
-use real3d::save::{save, load};
+use real3d::save::{load, save_error, SaveError, SaveSlots, Slot};
+use real3d::pieces::PieceSet;
+use real3d::generator::BagGenerator;
+use real3d::mode::{format_time, GameMode};
+use real3d::sim::{Input, Simulation};
+use real3d::ai::{Bot, Weights};
+use real3d::replay::Replay;
+use real3d::puzzle::{Puzzle, PuzzleStatus, PUZZLE_DIR};
+use std::sync::Arc;
 
+// number keys that pick a slot in the load menu, in menu order
+const SLOT_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+// gameplay keys and what they do, checked in this order with at most one per frame
+const KEYMAP: [(KeyCode, Input); 15] = [
+    (KeyCode::D, Input::Left),
+    (KeyCode::A, Input::Right),
+    (KeyCode::W, Input::Up),
+    (KeyCode::S, Input::Down),
+    (KeyCode::I, Input::Rotate(Axis::X, RotateDir::CounterClockwise)),
+    (KeyCode::K, Input::Rotate(Axis::X, RotateDir::Clockwise)),
+    (KeyCode::J, Input::Rotate(Axis::Y, RotateDir::CounterClockwise)),
+    (KeyCode::L, Input::Rotate(Axis::Y, RotateDir::Clockwise)),
+    (KeyCode::U, Input::Rotate(Axis::Z, RotateDir::CounterClockwise)),
+    (KeyCode::O, Input::Rotate(Axis::Z, RotateDir::Clockwise)),
+    (KeyCode::Space, Input::HardDrop),
+    (KeyCode::C, Input::Hold),
+    (KeyCode::Down, Input::SoftDrop),
+    (KeyCode::Z, Input::Undo),
+    (KeyCode::Y, Input::Redo),
+];
 
 #[derive(Clone, Debug)]
 pub struct Blocks {
     pub vec: Vec<Block>,
+    // landing preview of the falling piece
+    pub ghost: Vec<Block>,
 }
 
 impl Blocks {
@@ -84,7 +125,54 @@ impl Blocks {
                 });
             }
         }
-        Self { vec: blocks }
+        // upcoming pieces stacked beside the well, next one on top
+        for (k, shape) in grid.queue.iter().enumerate() {
+            let corner = GridCoord::new(grid.size.x + 1, grid.size.y - 4 * (k as i32 + 1), 0);
+            blocks.extend(Self::preview(&grid.make_tetris(*shape), corner + grid.origin));
+        }
+        // held piece on the other side of the well
+        if let Some(shape) = grid.held {
+            let corner = GridCoord::new(-4, grid.size.y - 4, 0);
+            blocks.extend(Self::preview(&grid.make_tetris(shape), corner + grid.origin));
+        }
+        // pending garbage meter just outside the well, one block per plane on its way
+        for y in 0..(grid.pending_garbage_planes() as i32).min(grid.size.y) {
+            blocks.push(Block {
+                c: GridCoord::new(-1, y, 0) + grid.origin,
+                color: TetrisColor::Red,
+            });
+        }
+        let mut ghost = vec![];
+        if let Some(current) = grid.tetris.get(grid.current) {
+            if current.falling && !grid.end {
+                for b in grid.landing_blocks(grid.current) {
+                    // skip cells the piece is already in so the ghost doesn't fight with it
+                    if !current.blocks.iter().any(|c| c.c == b.c) {
+                        ghost.push(Block {
+                            c: b.c + grid.origin,
+                            color: b.color,
+                        });
+                    }
+                }
+            }
+        }
+        Self { vec: blocks, ghost }
+    }
+
+    // piece moved so its lowest corner sits at corner, for showing pieces outside the well
+    fn preview(t: &Tetris, corner: GridCoord) -> Vec<Block> {
+        let min = GridCoord::new(
+            t.blocks.iter().map(|b| b.c.x).min().unwrap_or(0),
+            t.blocks.iter().map(|b| b.c.y).min().unwrap_or(0),
+            t.blocks.iter().map(|b| b.c.z).min().unwrap_or(0),
+        );
+        t.blocks
+            .iter()
+            .map(|b| Block {
+                c: corner + (b.c - min),
+                color: b.color,
+            })
+            .collect()
     }
 
     fn from_serialized(grid: &SerializableGrid, origin: cgmath::Vector3<i32>) -> Self {
@@ -98,7 +186,8 @@ impl Blocks {
         }
 
         Self {
-            vec: v
+            vec: v,
+            ghost: vec![],
         }
     }
 
@@ -121,6 +210,17 @@ impl Blocks {
                 },
             )
         }
+        // ghosts go last so the solid blocks behind them are already drawn
+        for b in self.ghost.iter() {
+            igs.render(
+                rules.ghost_model,
+                real3d::render::InstanceRaw {
+                    model: (Mat4::from_translation(b.c.to_vec().cast::<f32>().unwrap())
+                        * Mat4::from_nonuniform_scale(0.5, 0.5, 0.5))
+                        .into(),
+                },
+            )
+        }
 
         // igs.render_batch(
         //     rules.box_model,
@@ -156,7 +256,21 @@ impl Base {
 struct Game {
     blocks: Blocks,
     other_blocks: Option<Blocks>,
-    grid: Grid,
+    sim: Simulation,
+    // plays instead of the keyboard when started with --bot
+    bot: Option<Bot>,
+    // every game is recorded, and saved when it ends
+    replay: Replay,
+    replay_saved: bool,
+    // replay being played back, from --replay
+    playback: Option<Replay>,
+    // puzzle being played, from --puzzle
+    puzzle: Option<Puzzle>,
+    slots: SaveSlots,
+    // slot Enter saves to, the one the game was loaded from
+    slot: String,
+    // slots shown by the load menu while it is open
+    menu: Option<Vec<Slot>>,
     base: Base,
     other_bases: Vec<Base>,
     light: Light,
@@ -171,14 +285,84 @@ struct GameData {
     base_model: real3d::assets::ModelRef,
     other_base_model: real3d::assets::ModelRef,
     tetris_models: Vec<real3d::assets::ModelRef>,
+    ghost_model: real3d::assets::ModelRef,
+    // shapes used for new games, saved games keep their own
+    pieces: Arc<PieceSet>,
 }
 
 impl Game {
+    // open the load menu listing the slots, or close it
+    fn toggle_menu(&mut self) {
+        if self.menu.take().is_some() {
+            println!("Load menu closed");
+            return;
+        }
+        let slots = match self.slots.list() {
+            Ok(slots) => slots,
+            Err(e) => {
+                println!("{:?}", e);
+                return;
+            }
+        };
+        if slots.is_empty() {
+            println!("No saved games in {}", self.slots.dir.display());
+            return;
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        println!("Load which game? Tab to close");
+        for (i, slot) in slots.iter().take(SLOT_KEYS.len()).enumerate() {
+            let m = match &slot.meta {
+                Ok(m) => m,
+                Err(e) => {
+                    println!("  {}: {} - cannot be read ({})", i + 1, slot.name, e);
+                    continue;
+                }
+            };
+            println!(
+                "  {}: {} - {} Score: {} Level: {} Played: {} Seed: {} Saved {}",
+                i + 1,
+                slot.name,
+                m.mode.name(),
+                m.points,
+                m.level,
+                format_time(m.frames),
+                m.seed,
+                format_age(now.saturating_sub(m.saved_at))
+            );
+        }
+        self.menu = Some(slots);
+    }
+
+    // load the i-th slot of the open menu, which then becomes the slot Enter saves to
+    fn load_from_menu(&mut self, i: usize) {
+        let slot = match self.menu.as_ref().and_then(|m| m.get(i)) {
+            Some(slot) => slot.name.clone(),
+            None => return,
+        };
+        match self.slots.load(&slot) {
+            Ok(grid) => {
+                self.sim = Simulation::new(grid);
+                self.replay = Replay::new(&self.sim.grid);
+                self.replay_saved = false;
+                self.playback = None;
+                self.puzzle = None;
+                practice_history(&mut self.sim.grid, &self.server);
+                println!("Loaded slot {}. Mode: {} Seed: {}", slot, self.sim.grid.mode.name(), self.sim.grid.seed);
+                self.slot = slot;
+                self.menu = None;
+                self.recalc_blocks();
+            }
+            Err(e) => println!("{:?}", e),
+        }
+    }
+
     fn recalc_blocks(&mut self) {
         // if self.multiplayer_init {
         //     self.blocks = Blocks::new(&self.grid, -5);
         // } else {
-            self.blocks = Blocks::new(&self.grid, self.multiplayer_offset);
+            self.blocks = Blocks::new(&self.sim.grid, self.multiplayer_offset);
         // }
     }
 }
@@ -205,13 +389,51 @@ impl real3d::Game for Game {
             engine.load_model("block-yellow.obj"),
             engine.load_model("block.obj"),
         ];
+        let ghost_model = engine.load_model("block-ghost.obj");
 
         engine.set_ambient(1.0);
+        let pieces = match PieceSet::load("content/pieces.json") {
+            // share the built in set when it is unchanged, so saves and updates skip comparing it
+            Ok(p) if p.is_classic() => PieceSet::classic(),
+            Ok(p) => Arc::new(p),
+            Err(e) => {
+                println!("{:?}\nUsing the classic pieces", e);
+                PieceSet::classic()
+            }
+        };
         // let mut grid = Grid::new(cgmath::Vector3::<i32>::new(-4, 1, -3));
-        let mut grid = load(
-            "tetris_save.json",
-            cgmath::Vector3::<i32>::new(-4, 1, -3),
-        );
+        // a seed on the command line replays that exact game instead of loading the save
+        let slots = SaveSlots::user();
+        // --slot picks a slot, otherwise carry on from the last save
+        let slot = match slot_arg() {
+            Some(slot) => slot,
+            None => match slots.list() {
+                Ok(list) => match list.iter().find(|s| s.meta.is_ok()) {
+                    Some(s) => s.name.clone(),
+                    None => DEFAULT_SLOT.to_string(),
+                },
+                Err(_) => DEFAULT_SLOT.to_string(),
+            },
+        };
+        let mut grid = match seed_arg() {
+            Some(seed) => new_grid(&pieces, GridSize::default(), seed),
+            None => load_slot(&slots, &slot, &pieces),
+        };
+        let puzzle = puzzle_arg(&pieces);
+        if let Some(p) = &puzzle {
+            grid = p.to_grid(cgmath::Vector3::<i32>::new(-4, 1, -3), pieces.clone());
+            println!("{}: {}", p.name, p.description);
+        }
+        let mut playback = replay_arg();
+        match playback.as_ref().map(|r| r.start.to_grid()) {
+            Some(Ok(g)) => grid = g,
+            Some(Err(e)) => {
+                println!("{:?}", e);
+                playback = None;
+            }
+            None => {}
+        }
+        println!("Mode: {} Seed: {}", grid.mode.name(), grid.seed);
 
         let blocks = Blocks::new(&grid, 0);
 
@@ -234,12 +456,23 @@ impl real3d::Game for Game {
         let audio = Audio::new(audio_manager, sound_handles);
         let mut server = Server::new();
         server.connect("45.10.152.68:16512");
+        practice_history(&mut grid, &server);
+        let replay = Replay::new(&grid);
         let camera_controller = CameraController::new(1.0);
         (
             Self {
                 camera_controller,
                 blocks,
-                grid,
+                sim: Simulation::new(grid),
+                bot: if has_arg("--bot") { Some(Bot::new(Weights::default())) } else { None },
+                replay,
+                // watching a replay is not a new game, so there is nothing to save when it ends
+                replay_saved: playback.is_some(),
+                playback,
+                puzzle,
+                slots,
+                slot,
+                menu: None,
                 base,
                 audio,
                 light,
@@ -253,6 +486,8 @@ impl real3d::Game for Game {
                 base_model,
                 other_base_model,
                 tetris_models,
+                ghost_model,
+                pieces,
             },
         )
     }
@@ -272,68 +507,126 @@ impl real3d::Game for Game {
         }
     }
 
-    fn update(&mut self, _rules: &Self::StaticData, engine: &mut Engine) {
+    fn update(&mut self, rules: &Self::StaticData, engine: &mut Engine) {
+        use rand::Rng;
         self.camera_controller.update(engine);
         // background audio
         self.audio
             .play(SoundID(0), true, Some(0.0), AlreadyPlayingAction::Nothing);
-        let curr = self.grid.current;
-
-        // when current piece lands, check to clear plane and spawn new piece
-        if !self.grid.tetris[curr].falling && !self.grid.end {
-            // check if plane needs to be cleared
-            let planes = self.grid.check_planes();
-            // println!("planes: {:?}", planes);
-            if !planes.is_empty() {
-                for p in planes {
-                    self.grid.clear_plane(p);
-                    self.recalc_blocks();
-                    self.audio
-                        .play(SoundID(1), true, Some(0.0), AlreadyPlayingAction::Nothing);
+        // a replay presses the recorded keys, so it goes through exactly the same code as a player
+        if let Some(replay) = &self.playback {
+            if self.sim.frame < replay.length {
+                for (key, _) in KEYMAP.iter() {
+                    engine.events.clear_key(*key);
                 }
-            } else if self.grid.tetris.len() % 15 == 0 {
-                self.grid.clear_plane(2);
-                self.recalc_blocks();
-                self.audio
-                    .play(SoundID(1), false, Some(0.0), AlreadyPlayingAction::Nothing);
+                for input in replay.inputs_at(self.sim.frame) {
+                    if let Some((key, _)) = KEYMAP.iter().find(|(_, i)| i == input) {
+                        engine.events.press_key(*key);
+                    }
+                }
+            } else {
+                println!("Replay finished");
+                self.playback = None;
             }
-            // spawn new piece
-            self.grid.add_tetris();
-            self.recalc_blocks();
-        }
-
-        if self.grid.tetris[curr].falling && engine.frame % 30 == 0 {
-            self.grid.lower_tetris(curr);
-            self.recalc_blocks();
-        }
-
-        if engine.events.key_pressed(KeyCode::D) {
-            self.grid.move_xz(curr, 0);
-            self.recalc_blocks();
-        } else if engine.events.key_pressed(KeyCode::A) {
-            self.grid.move_xz(curr, 1);
-            self.recalc_blocks();
-        } else if engine.events.key_pressed(KeyCode::W) {
-            self.grid.move_xz(curr, 2);
-            self.recalc_blocks();
-        } else if engine.events.key_pressed(KeyCode::S) {
-            self.grid.move_xz(curr, 3);
-            self.recalc_blocks();
-        } else if engine.events.key_held(KeyCode::Down) {
-            self.grid.lower_tetris(curr);
-            self.recalc_blocks();
-        } else if engine.events.key_pressed(KeyCode::Return) {
-            if self.grid.end {
-                save(&Grid::new(self.grid.origin), "tetris_save.json");
+        }
+
+        let mut inputs = vec![];
+        // soft drop repeats while held, everything else once per press
+        if let Some((_, input)) = KEYMAP.iter().find(|(key, input)| {
+            if *input == Input::SoftDrop {
+                engine.events.key_held(*key)
             } else {
-                save(&self.grid, "tetris_save.json");
+                engine.events.key_pressed(*key)
+            }
+        }) {
+            inputs.push(*input);
+        }
+        if self.playback.is_none() {
+            if let Some(bot) = &mut self.bot {
+                inputs = bot.next_inputs(&self.sim.grid);
+            }
+        }
+        // attacks that arrived since the last frame, or the recorded ones when watching a replay
+        let garbage = self.server.take_garbage();
+        match &self.playback {
+            Some(replay) => {
+                let recorded = replay.inputs_at(self.sim.frame).iter();
+                inputs.extend(recorded.filter(|i| matches!(i, Input::Garbage(_))));
             }
-            println!("Game saved");
+            None => inputs.extend(garbage.into_iter().map(Input::Garbage)),
+        }
+        let events = self.sim.step_with(&inputs);
+        self.replay.record(&inputs);
+        if let Some(puzzle) = &self.puzzle {
+            if !self.sim.grid.end {
+                match puzzle.status(&self.sim.grid) {
+                    PuzzleStatus::Solved => {
+                        self.sim.grid.won = true;
+                        self.sim.grid.end_game();
+                    }
+                    PuzzleStatus::Failed => self.sim.grid.end_game(),
+                    PuzzleStatus::InProgress => {}
+                }
+            }
+        }
+        if self.sim.grid.end && !self.replay_saved {
+            match self.replay.save_new(Replay::user_dir()) {
+                Ok(path) => println!("Replay saved to {}", path.display()),
+                Err(e) => println!("{:?}", e),
+            }
+            self.replay_saved = true;
+        }
+        let score = self.sim.grid.score;
+        if events.planes > 0 {
+            println!("Score: {} Level: {} Planes: {}", score.points, score.level, score.planes_cleared);
+            self.audio
+                .play(SoundID(1), true, Some(0.0), AlreadyPlayingAction::Nothing);
+        }
+        if !events.chains.is_empty() {
+            println!("Chain x{} Score: {}", events.chains.len(), score.points);
+        }
+        if events.attack > 0 {
+            let mut rng = rand::thread_rng();
+            let size = self.sim.grid.size;
+            self.server.send_garbage(Garbage {
+                planes: events.attack,
+                hole: [rng.gen_range(0..size.x), rng.gen_range(0..size.z)],
+            });
+        }
+        if events.assisted {
+            self.audio
+                .play(SoundID(1), false, Some(0.0), AlreadyPlayingAction::Nothing);
+        }
+        self.recalc_blocks();
+
+        if engine.events.key_pressed(KeyCode::Return) {
+            let saved = if self.sim.grid.end {
+                self.slots.save(&self.slot, &new_grid(&rules.pieces, self.sim.grid.size, random_seed()))
+            } else {
+                self.slots.save(&self.slot, &self.sim.grid)
+            };
+            match saved {
+                Ok(_) => println!("Game saved to slot {}", self.slot),
+                Err(e) => println!("{:?}", e),
+            }
+        } else if engine.events.key_pressed(KeyCode::Tab) {
+            self.toggle_menu();
+        } else if let Some(i) = SLOT_KEYS.iter().position(|k| engine.events.key_pressed(*k)) {
+            self.load_from_menu(i);
         } else if engine.events.key_pressed(KeyCode::N) {
-            println!("Game restarted");
-            self.grid = Grid::new(cgmath::Vector3::<i32>::new(-4, 1, -3));
+            // a puzzle restarts from its starting stack
+            let grid = match &self.puzzle {
+                Some(p) => p.to_grid(cgmath::Vector3::<i32>::new(-4, 1, -3), rules.pieces.clone()),
+                None => new_grid(&rules.pieces, self.sim.grid.size, random_seed()),
+            };
+            self.sim = Simulation::new(grid);
+            self.replay = Replay::new(&self.sim.grid);
+            self.replay_saved = false;
+            self.playback = None;
+            practice_history(&mut self.sim.grid, &self.server);
+            println!("Game restarted. Seed: {}", self.sim.grid.seed);
         }
-        let other = self.server.update_grid(&self.grid);
+        let other = self.server.update_grid(&self.sim.grid);
         if other.len() > 0 {
             self.other_blocks = Some(Blocks::from_serialized(&other[0], cgmath::Vector3::<i32>::new(-15, 1, -3)));
             self.other_bases = vec![Base { origin: Vec3::new(-11.0, 0.0, 0.0), use_other: true }];
@@ -365,6 +658,183 @@ impl real3d::Game for Game {
     }
 }
 
+// slot used when there are no saves yet
+const DEFAULT_SLOT: &str = "quicksave";
+
+// the game in slot, falling back to a save from before slots and then to a new game
+fn load_slot(slots: &SaveSlots, slot: &str, pieces: &Arc<PieceSet>) -> Grid {
+    let err = match slots.load(slot) {
+        Ok(grid) => return grid,
+        Err(e) => e,
+    };
+    if save_error(&err) == Some(SaveError::Missing) {
+        match load("tetris_save.json") {
+            Ok(grid) => return grid,
+            Err(e) if save_error(&e) == Some(SaveError::Missing) => {}
+            Err(e) => println!("{:?}\nStarting a new game", e),
+        }
+    } else {
+        // no save is the usual first run, anything else is worth telling the player about
+        println!("{:?}\nStarting a new game", err);
+    }
+    new_grid(pieces, GridSize::default(), random_seed())
+}
+
+// how long ago, in the largest whole unit
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", secs / 60),
+        3600..=86399 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+fn new_grid(pieces: &Arc<PieceSet>, size: GridSize, seed: u64) -> Grid {
+    let mut grid = Grid::with_pieces(
+        cgmath::Vector3::<i32>::new(-4, 1, -3),
+        size,
+        seed,
+        pieces.clone(),
+        Box::new(BagGenerator::new()),
+    );
+    grid.gravity = gravity_arg();
+    grid.mode = mode_arg();
+    grid
+}
+
+// value of --mode, marathon if not given
+fn mode_arg() -> GameMode {
+    let args: Vec<String> = std::env::args().collect();
+    let i = match args.iter().position(|a| a == "--mode") {
+        Some(i) => i,
+        None => return GameMode::Marathon,
+    };
+    match args.get(i + 1).and_then(|s| GameMode::from_name(s)) {
+        Some(GameMode::Puzzle) => {
+            println!("puzzles are started with --puzzle <name>, using marathon");
+            GameMode::Marathon
+        }
+        Some(mode) => mode,
+        None => {
+            println!("--mode needs marathon, sprint, ultra, zen or practice, using marathon");
+            GameMode::Marathon
+        }
+    }
+}
+
+// puzzle named by --puzzle, loaded from the puzzle pack
+fn puzzle_arg(pieces: &PieceSet) -> Option<Puzzle> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == "--puzzle")?;
+    let name = match args.get(i + 1) {
+        Some(name) => name,
+        None => {
+            println!("--puzzle needs a puzzle name");
+            return None;
+        }
+    };
+    let path = std::path::Path::new(PUZZLE_DIR).join(format!("{}.json", name));
+    if path.exists() {
+        match Puzzle::load(&path, pieces) {
+            Ok(puzzle) => return Some(puzzle),
+            Err(e) => {
+                println!("{:?}", e);
+                return None;
+            }
+        }
+    }
+    println!("No puzzle named {}, the puzzles are:", name);
+    if let Ok(entries) = std::fs::read_dir(PUZZLE_DIR) {
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.path().file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .collect();
+        names.sort();
+        for name in names {
+            println!("  {}", name);
+        }
+    }
+    None
+}
+
+// practice games can undo placements, but not against someone else
+fn practice_history(grid: &mut Grid, server: &Server) {
+    if grid.mode != GameMode::Practice {
+        return;
+    }
+    if server.connected {
+        println!("Undo is off in multiplayer");
+    } else {
+        grid.enable_history();
+    }
+}
+
+// replay named by --replay, if it loads
+fn replay_arg() -> Option<Replay> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == "--replay")?;
+    match args.get(i + 1).map(Replay::load) {
+        Some(Ok(replay)) => Some(replay),
+        Some(Err(e)) => {
+            println!("{:?}", e);
+            None
+        }
+        None => {
+            println!("--replay needs a file name");
+            None
+        }
+    }
+}
+
+fn has_arg(name: &str) -> bool {
+    std::env::args().any(|a| a == name)
+}
+
+// value of --gravity, naive if not given
+fn gravity_arg() -> GravityMode {
+    let args: Vec<String> = std::env::args().collect();
+    let i = match args.iter().position(|a| a == "--gravity") {
+        Some(i) => i,
+        None => return GravityMode::Naive,
+    };
+    match args.get(i + 1).map(|s| s.as_str()) {
+        Some("naive") => GravityMode::Naive,
+        Some("sticky") => GravityMode::Sticky,
+        Some("cascade") => GravityMode::Cascade,
+        _ => {
+            println!("--gravity needs naive, sticky or cascade, using naive");
+            GravityMode::Naive
+        }
+    }
+}
+
+// value of --slot, if given
+fn slot_arg() -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == "--slot")?;
+    match args.get(i + 1) {
+        Some(slot) => Some(slot.clone()),
+        None => {
+            println!("--slot needs a slot name");
+            None
+        }
+    }
+}
+
+// value of --seed, if given
+fn seed_arg() -> Option<u64> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == "--seed")?;
+    match args.get(i + 1).map(|s| s.parse::<u64>()) {
+        Some(Ok(seed)) => Some(seed),
+        _ => {
+            println!("--seed needs a number, using a random seed");
+            None
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
     let window = winit::window::WindowBuilder::new().with_title("Tetris 3D");
@@ -105,6 +105,18 @@ impl Events {
     // the down, and we want to delay the up until the following simulation step.
     // In this setting, down up down could lead to a missed up event, but that
     // seems better than the alternative of missing down events.
+    // press k as if it went down this frame, for replays and other scripted input
+    pub fn press_key(&mut self, k: KeyCode) {
+        self.released.remove(&k);
+        self.held.insert(k, 0);
+    }
+
+    // forget any press or release of k, so only scripted input counts
+    pub fn clear_key(&mut self, k: KeyCode) {
+        self.held.remove(&k);
+        self.released.remove(&k);
+    }
+
     pub fn key_pressed(&self, k: KeyCode) -> bool {
         self.held.get(&k).map(|num| *num == 0).unwrap_or(false)
     }
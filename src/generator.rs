@@ -0,0 +1,112 @@
+use crate::grid::TetrisShape;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+// decides which shape spawns next
+pub trait PieceGenerator {
+    // shapes are indices below count
+    fn next_shape(&mut self, count: usize, rng: &mut dyn RngCore) -> TetrisShape;
+    // everything needed to pick up where this generator left off, for saves
+    fn state(&self) -> GeneratorState;
+}
+
+// every shape is equally likely every time, so the same shape can repeat or go missing for a while
+pub struct UniformGenerator;
+
+impl PieceGenerator for UniformGenerator {
+    fn next_shape(&mut self, count: usize, rng: &mut dyn RngCore) -> TetrisShape {
+        rng.gen_range(0..count)
+    }
+
+    fn state(&self) -> GeneratorState {
+        GeneratorState::Uniform
+    }
+}
+
+// deals every shape once in a shuffled order before refilling, like the 7-bag in 2D tetris
+pub struct BagGenerator {
+    bag: Vec<TetrisShape>,
+}
+
+impl BagGenerator {
+    pub fn new() -> Self {
+        Self { bag: vec![] }
+    }
+}
+
+impl PieceGenerator for BagGenerator {
+    fn next_shape(&mut self, count: usize, rng: &mut dyn RngCore) -> TetrisShape {
+        if self.bag.is_empty() {
+            self.bag = (0..count).collect();
+            self.bag.shuffle(rng);
+        }
+        self.bag.pop().unwrap()
+    }
+
+    fn state(&self) -> GeneratorState {
+        GeneratorState::Bag {
+            remaining: self.bag.clone(),
+        }
+    }
+}
+
+// deals a fixed list of shapes in order, starting over at the end, for puzzles
+pub struct SequenceGenerator {
+    shapes: Vec<TetrisShape>,
+    next: usize,
+}
+
+impl SequenceGenerator {
+    pub fn new(shapes: Vec<TetrisShape>) -> Self {
+        Self { shapes, next: 0 }
+    }
+}
+
+impl PieceGenerator for SequenceGenerator {
+    fn next_shape(&mut self, count: usize, _rng: &mut dyn RngCore) -> TetrisShape {
+        if self.shapes.is_empty() {
+            return 0;
+        }
+        let shape = self.shapes[self.next % self.shapes.len()];
+        self.next += 1;
+        shape.min(count.saturating_sub(1))
+    }
+
+    fn state(&self) -> GeneratorState {
+        GeneratorState::Sequence {
+            shapes: self.shapes.clone(),
+            next: self.next,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum GeneratorState {
+    Uniform,
+    // shapes still in the bag, dealt from the back
+    Bag { remaining: Vec<TetrisShape> },
+    // the whole list and how many have been dealt
+    Sequence { shapes: Vec<TetrisShape>, next: usize },
+}
+
+impl Default for GeneratorState {
+    fn default() -> Self {
+        GeneratorState::Bag { remaining: vec![] }
+    }
+}
+
+impl GeneratorState {
+    pub fn to_generator(&self) -> Box<dyn PieceGenerator> {
+        match self {
+            GeneratorState::Uniform => Box::new(UniformGenerator),
+            GeneratorState::Bag { remaining } => Box::new(BagGenerator {
+                bag: remaining.clone(),
+            }),
+            GeneratorState::Sequence { shapes, next } => Box::new(SequenceGenerator {
+                shapes: shapes.clone(),
+                next: *next,
+            }),
+        }
+    }
+}
@@ -1,19 +1,114 @@
 use crate::assets::ModelRef;
 use crate::geom::*;
 use crate::geom::*;
+use crate::generator::{BagGenerator, PieceGenerator};
+use crate::mode::GameMode;
+use crate::pieces::{PieceDef, PieceSet};
+use crate::score::Score;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use cgmath::Point3;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 pub type GridCoord = cgmath::Point3<i32>;
+pub type GridOffset = cgmath::Vector3<i32>;
 pub type TetrisBounds = cgmath::Point3<i32>; // top view, y is lowest point
 
 use serde::{Deserialize, Serialize, Serializer};
 use serde::ser::SerializeTuple;
 
 pub const GRID_SCALE: f32 = 32.0;
-pub const GRID_X_MAX: i32 = 8;
-pub const GRID_Y_MAX: i32 = 16;
-pub const GRID_Z_MAX: i32 = 8;
+// how many upcoming pieces are shown
+pub const PREVIEW_LEN: usize = 3;
+// frames a resting piece waits before locking, and how many moves can restart the wait
+pub const DEFAULT_LOCK_DELAY: usize = 30;
+pub const DEFAULT_LOCK_RESETS: usize = 15;
+// placements kept for undo in practice mode
+pub const HISTORY_LEN: usize = 100;
+
+// dimensions of the well in blocks, y is up
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct GridSize {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Default for GridSize {
+    fn default() -> Self {
+        Self::new(8, 16, 8)
+    }
+}
+
+impl std::ops::Index<usize> for GridSize {
+    type Output = i32;
+
+    fn index(&self, axis: usize) -> &i32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("grid axis out of range: {}", axis),
+        }
+    }
+}
+
+impl GridSize {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        debug_assert!(x > 0 && y > 0 && z > 0);
+        Self { x, y, z }
+    }
+
+    // number of blocks in the whole grid
+    pub fn len(&self) -> usize {
+        (self.x * self.y * self.z) as usize
+    }
+
+    // number of blocks in one horizontal plane
+    pub fn plane_len(&self) -> usize {
+        (self.x * self.z) as usize
+    }
+
+    pub fn contains(&self, c: GridCoord) -> bool {
+        0 <= c.x && c.x < self.x && 0 <= c.y && c.y < self.y && 0 <= c.z && c.z < self.z
+    }
+
+    pub fn xyz_to_index(&self, x: i32, y: i32, z: i32) -> usize {
+        debug_assert!(0 <= x && x < self.x);
+        debug_assert!(0 <= y && y < self.y);
+        debug_assert!(0 <= z && z < self.z);
+        (z + x * self.z + y * self.z * self.x) as usize
+    }
+
+    pub fn index_to_xyz(&self, i: usize) -> (i32, i32, i32) {
+        let i = i as i32;
+        debug_assert!(i < self.x * self.y * self.z);
+
+        let z = i % self.z;
+        let tmp = i / self.z;
+
+        let x = tmp % self.x;
+        let y = tmp / self.x;
+
+        debug_assert!(0 <= x && x < self.x);
+        debug_assert!(0 <= y && y < self.y);
+        debug_assert!(0 <= z && z < self.z);
+
+        (x, y, z)
+    }
+
+    // takes a grid coordinate, returns index in grid struct
+    pub fn coord_to_index(&self, c: GridCoord) -> usize {
+        self.xyz_to_index(c.x, c.y, c.z)
+    }
+
+    // takes index in grid struct, returns grid coordinate
+    pub fn index_to_coord(&self, i: usize) -> GridCoord {
+        let (x, y, z) = self.index_to_xyz(i);
+        GridCoord::new(x, y, z)
+    }
+}
 
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -39,187 +134,183 @@ pub enum TetrisColor {
     Mix,
 }
 
+// index of a shape in the grid's piece set
+pub type TetrisShape = usize;
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Tetris {
     pub blocks: Vec<Block>,
     pub falling: bool,
+    pub shape: TetrisShape,
+    // point the piece rotates around, either a cell center or a cell corner
+    pub pivot: Pos3,
+    // frames spent resting on something without locking
+    pub lock_timer: usize,
+    // times a move or rotation has restarted the lock timer
+    pub lock_resets: usize,
 }
 
-impl Tetris {
-    fn gen_random_tetris() -> Self {
-        use TetrisColor::*;
-        let mut rng = rand::thread_rng();
-        let shape: usize = rng.gen_range(0..6);
-        // println!("Shape {}", shape);
-        match shape {
-            0 => {
-                // 2x2x2 cube
-                Tetris {
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(5, 14, 4),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 15, 4),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 15, 4),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 15, 5),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 14, 5),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 15, 5),
-                            color: Red,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 5),
-                            color: Red,
-                        },
-                    ],
-                    falling: true,
-                }
-            }
-            1 => {
-                Tetris {
-                    // 4x1x1 line
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(4, 12, 4),
-                            color: Green,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 13, 4),
-                            color: Green,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Green,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 15, 4),
-                            color: Green,
-                        },
-                    ],
-                    falling: true,
-                }
-            }
-            2 => {
-                Tetris {
-                    // upside down T
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(5, 14, 4),
-                            color: Blue,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 15, 4),
-                            color: Blue,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Blue,
-                        },
-                        Block {
-                            c: GridCoord::new(6, 15, 4),
-                            color: Blue,
-                        },
-                    ],
-                    falling: true,
-                }
-            }
-            3 => {
-                // corner
-                Tetris {
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Cyan,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 15, 4),
-                            color: Cyan,
-                        },
-                        Block {
-                            c: GridCoord::new(3, 14, 4),
-                            color: Cyan,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 5),
-                            color: Cyan,
-                        },
-                    ],
-                    falling: true,
-                }
-            }
-            4 => {
-                // Z
-                Tetris {
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(4, 13, 4),
-                            color: Magenta,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Magenta,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 14, 4),
-                            color: Magenta,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 15, 4),
-                            color: Magenta,
-                        },
-                    ],
-                    falling: true,
-                }
+// axis a piece is rotated around
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+// rotation direction, looking down the positive axis towards the origin
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RotateDir {
+    Clockwise,
+    CounterClockwise,
+}
+
+// which kick table a shape uses
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Kicks {
+    // shapes that look the same after any rotation, like the cube, never need to kick
+    None,
+    Standard,
+    // long shapes like the line need two cells of clearance
+    Long,
+}
+
+// offsets tried in order when a rotation is blocked, like SRS wall kicks in 2D
+// sideways kicks stay in the plane of rotation and try the rotation direction first,
+// then floor kicks lift the piece, and ceiling kicks are a last resort at the top
+pub fn kick_table(kicks: Kicks, axis: Axis, dir: RotateDir) -> Vec<GridOffset> {
+    let mut offsets = vec![GridOffset::new(0, 0, 0)];
+    let reach = match kicks {
+        Kicks::None => return offsets,
+        Kicks::Standard => 1,
+        Kicks::Long => 2,
+    };
+    let s = match dir {
+        RotateDir::CounterClockwise => 1,
+        RotateDir::Clockwise => -1,
+    };
+    let sideways = match axis {
+        Axis::X => vec![GridOffset::unit_z()],
+        Axis::Y => vec![GridOffset::unit_x(), GridOffset::unit_z()],
+        Axis::Z => vec![GridOffset::unit_x()],
+    };
+    let up = GridOffset::unit_y();
+
+    for d in 1..=reach {
+        for a in &sideways {
+            offsets.push(a * s * d);
+            offsets.push(a * -s * d);
+        }
+        // rotating around Y never changes height, so only X and Z rotations kick off the floor
+        if axis != Axis::Y {
+            offsets.push(up * d);
+            for a in &sideways {
+                offsets.push(up * d + a * s * d);
+                offsets.push(up * d + a * -s * d);
             }
-            5 => {
-                Tetris {
-                    // L
-                    blocks: vec![
-                        Block {
-                            c: GridCoord::new(4, 13, 4),
-                            color: Yellow,
-                        },
-                        Block {
-                            c: GridCoord::new(5, 13, 4),
-                            color: Yellow,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 14, 4),
-                            color: Yellow,
-                        },
-                        Block {
-                            c: GridCoord::new(4, 15, 4),
-                            color: Yellow,
-                        },
-                    ],
-                    falling: true,
+        }
+    }
+    if axis != Axis::Y {
+        for d in 1..=reach {
+            offsets.push(up * -d);
+        }
+    }
+    offsets
+}
+
+impl Tetris {
+    // an already landed piece, for stacks and garbage that were never dealt as a shape
+    pub fn resting(blocks: Vec<Block>) -> Self {
+        Self {
+            blocks,
+            falling: false,
+            shape: 0,
+            pivot: Pos3::new(0.0, 0.0, 0.0),
+            lock_timer: 0,
+            lock_resets: 0,
+        }
+    }
+
+    // move every block and the pivot, without checking the grid
+    pub fn translate(&mut self, v: GridOffset) {
+        for b in self.blocks.iter_mut() {
+            b.c += v;
+        }
+        self.pivot += v.cast::<f32>().unwrap();
+    }
+
+    // block coordinates after rotating 90 degrees around the pivot
+    pub fn rotated(&self, axis: Axis, dir: RotateDir) -> Vec<Block> {
+        let s = match dir {
+            RotateDir::CounterClockwise => 1.0,
+            RotateDir::Clockwise => -1.0,
+        };
+        self.blocks
+            .iter()
+            .map(|b| {
+                let v = b.c.cast::<f32>().unwrap() - self.pivot;
+                let r = match axis {
+                    Axis::X => Vec3::new(v.x, -s * v.z, s * v.y),
+                    Axis::Y => Vec3::new(s * v.z, v.y, -s * v.x),
+                    Axis::Z => Vec3::new(-s * v.y, s * v.x, v.z),
+                };
+                let c = self.pivot + r;
+                Block {
+                    c: GridCoord::new(c.x.round() as i32, c.y.round() as i32, c.z.round() as i32),
+                    color: b.color,
                 }
-            }
-            _ => Tetris {
-                blocks: vec![],
-                falling: false,
-            }, // will not be reached
+            })
+            .collect()
+    }
+
+    // spawn a piece of the given shape, laid out for the default grid
+    pub fn new(def: &PieceDef, shape: TetrisShape) -> Self {
+        let spawn = GridCoord::new(def.spawn[0], def.spawn[1], def.spawn[2]);
+        Tetris {
+            blocks: def
+                .cells
+                .iter()
+                .map(|c| Block {
+                    c: spawn + GridOffset::new(c[0], c[1], c[2]),
+                    color: def.color,
+                })
+                .collect(),
+            falling: true,
+            shape,
+            pivot: spawn.cast::<f32>().unwrap() + Vec3::new(def.pivot[0], def.pivot[1], def.pivot[2]),
+            lock_timer: 0,
+            lock_resets: 0,
         }
     }
 }
 
+// the six cells sharing a face with a cell
+const NEIGHBORS: [GridOffset; 6] = [
+    GridOffset::new(1, 0, 0),
+    GridOffset::new(-1, 0, 0),
+    GridOffset::new(0, 1, 0),
+    GridOffset::new(0, -1, 0),
+    GridOffset::new(0, 0, 1),
+    GridOffset::new(0, 0, -1),
+];
+
+// how blocks above a cleared plane come down
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GravityMode {
+    // everything above drops by exactly one plane per cleared plane, even if left floating
+    Naive,
+    // connected blocks fall together until something holds them up
+    Sticky,
+    // sticky, then clear any planes the fall filled and repeat until nothing changes
+    Cascade,
+}
+
+impl Default for GravityMode {
+    fn default() -> Self {
+        GravityMode::Naive
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
 pub enum GridBlock {
     Vacant,
@@ -235,25 +326,150 @@ impl GridBlock {
     }
 }
 
+// planes sent by an opponent, waiting to be pushed in under the stack
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Garbage {
+    pub planes: usize,
+    // the one (x, z) cell left open in each of its planes, wrapped into the receiving grid's size
+    pub hole: [i32; 2],
+}
+
 pub struct Grid {
     pub tetris: Vec<Tetris>,
     pub current: usize,
     pub origin: cgmath::Vector3<i32>,
     pub end: bool,
-    pub grid: [GridBlock; (GRID_X_MAX * GRID_Y_MAX * GRID_Z_MAX) as usize],
+    pub size: GridSize,
+    pub pieces: Arc<PieceSet>,
+    pub grid: Vec<GridBlock>,
+    pub score: Score,
+    pub generator: Box<dyn PieceGenerator>,
+    // shapes that spawn next, front first
+    pub queue: VecDeque<TetrisShape>,
+    // the same seed always deals the same pieces
+    pub seed: u64,
+    pub rng: ChaCha8Rng,
+    pub held: Option<TetrisShape>,
+    // only one hold per piece, reset when the next piece spawns
+    pub hold_used: bool,
+    pub lock_delay: usize,
+    pub max_lock_resets: usize,
+    pub gravity: GravityMode,
+    pub mode: GameMode,
+    // updates played so far, for timed modes
+    pub frames: usize,
+    // the game ended because the mode's goal was met rather than by topping out
+    pub won: bool,
+    // garbage received but not inserted yet, oldest first
+    pub pending_garbage: Vec<Garbage>,
+    // earlier placements for undo and redo, only kept in practice mode
+    pub history: Option<History>,
 }
 
-impl Grid {
-    pub fn new(origin: cgmath::Vector3<i32>) -> Self {
+// grid states at the moment each piece spawned, current piece last
+#[derive(Clone, Default)]
+pub struct History {
+    undo: VecDeque<Grid>,
+    redo: Vec<Grid>,
+}
+
+// the generator is rebuilt from its saved state since trait objects can't be cloned directly
+// history is left out, a copy is just the game as it is now
+impl Clone for Grid {
+    fn clone(&self) -> Self {
         Self {
-            tetris: vec![Tetris::gen_random_tetris()],
+            tetris: self.tetris.clone(),
+            current: self.current,
+            origin: self.origin,
+            end: self.end,
+            size: self.size,
+            pieces: self.pieces.clone(),
+            grid: self.grid.clone(),
+            score: self.score,
+            generator: self.generator.state().to_generator(),
+            queue: self.queue.clone(),
+            seed: self.seed,
+            rng: self.rng.clone(),
+            held: self.held,
+            hold_used: self.hold_used,
+            lock_delay: self.lock_delay,
+            max_lock_resets: self.max_lock_resets,
+            gravity: self.gravity,
+            mode: self.mode,
+            frames: self.frames,
+            won: self.won,
+            pending_garbage: self.pending_garbage.clone(),
+            history: None,
+        }
+    }
+}
+
+// fresh seed for a game that doesn't need to be reproduced
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+impl Grid {
+    pub fn new(origin: cgmath::Vector3<i32>, size: GridSize, seed: u64) -> Self {
+        Self::with_pieces(origin, size, seed, PieceSet::classic(), Box::new(BagGenerator::new()))
+    }
+
+    pub fn with_pieces(
+        origin: cgmath::Vector3<i32>,
+        size: GridSize,
+        seed: u64,
+        pieces: Arc<PieceSet>,
+        generator: Box<dyn PieceGenerator>,
+    ) -> Self {
+        let mut grid = Self {
+            tetris: vec![],
             current: 0,
             origin,
             end: false,
-            grid: [GridBlock::Vacant; (GRID_X_MAX * GRID_Y_MAX * GRID_Z_MAX) as usize],
+            size,
+            pieces,
+            grid: vec![GridBlock::Vacant; size.len()],
+            score: Score::new(),
+            generator,
+            queue: VecDeque::new(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            held: None,
+            hold_used: false,
+            lock_delay: DEFAULT_LOCK_DELAY,
+            max_lock_resets: DEFAULT_LOCK_RESETS,
+            gravity: GravityMode::Naive,
+            mode: GameMode::Marathon,
+            frames: 0,
+            won: false,
+            pending_garbage: vec![],
+            history: None,
+        };
+        grid.fill_queue();
+        grid.add_tetris();
+        grid
+    }
+
+    // top up the preview queue from the generator
+    pub fn fill_queue(&mut self) {
+        while self.queue.len() < PREVIEW_LEN {
+            self.queue.push_back(self.generator.next_shape(self.pieces.pieces.len(), &mut self.rng));
         }
     }
 
+    // a piece of the given shape, before it is moved to this grid's spawn point
+    pub fn make_tetris(&self, shape: TetrisShape) -> Tetris {
+        Tetris::new(&self.pieces.pieces[shape], shape)
+    }
+
+    // take the next shape off the queue
+    pub fn next_shape(&mut self) -> TetrisShape {
+        self.fill_queue();
+        let shape = self.queue.pop_front().unwrap();
+        self.fill_queue();
+        shape
+    }
+
     // pub fn index_occupied_by(&self, i:usize) -> Option<usize>{
     //     match self.grid[i]  {
     //         GridBlock::Vacant => None,
@@ -261,40 +477,24 @@ impl Grid {
     //     }
     // }
 
-    pub fn xyz_to_index(x: i32, y: i32, z: i32) -> usize {
-        debug_assert!(0 <= x && x < GRID_X_MAX);
-        debug_assert!(0 <= y && y < GRID_Y_MAX);
-        debug_assert!(0 <= z && z < GRID_Z_MAX);
-        (z + x * GRID_Z_MAX + y * GRID_Z_MAX * GRID_X_MAX) as usize
+    pub fn xyz_to_index(&self, x: i32, y: i32, z: i32) -> usize {
+        self.size.xyz_to_index(x, y, z)
     }
 
-    pub fn index_to_xyz(i: usize) -> (i32, i32, i32) {
-        let i = i as i32;
-        debug_assert!(i < GRID_X_MAX * GRID_Y_MAX * GRID_Z_MAX);
-
-        let z = i % GRID_Z_MAX;
-        let tmp = i / GRID_Z_MAX;
-
-        let x = tmp % GRID_X_MAX;
-        let y = tmp / GRID_X_MAX;
-
-        debug_assert!(0 <= x && x < GRID_X_MAX);
-        debug_assert!(0 <= y && y < GRID_Y_MAX);
-        debug_assert!(0 <= z && z < GRID_Z_MAX);
-
-        (x, y, z)
+    pub fn index_to_xyz(&self, i: usize) -> (i32, i32, i32) {
+        self.size.index_to_xyz(i)
     }
 
     pub fn get_plane(&self, y: i32) -> &[GridBlock] {
-        debug_assert!(0 <= y && y < GRID_Y_MAX);
-        &self.grid
-            [(y * GRID_Z_MAX * GRID_X_MAX) as usize..((y + 1) * GRID_Z_MAX * GRID_X_MAX) as usize]
+        debug_assert!(0 <= y && y < self.size.y);
+        let n = self.size.plane_len();
+        &self.grid[y as usize * n..(y as usize + 1) * n]
     }
 
     // check if any planes are full, return first full vec
     pub fn check_planes(&self) -> Vec<i32> {
         let mut planes = vec![];
-        for i in 0..GRID_Y_MAX {
+        for i in 0..self.size.y {
             let mut vacant = false;
             for g in self.get_plane(i) {
                 if *g == GridBlock::Vacant {
@@ -308,89 +508,433 @@ impl Grid {
         planes
     }
 
-    // clear blocks on plane and lower blocks above
-    // return new set of blocks for tetris piece
-    pub fn split_piece(&mut self, i: usize, y: i32) -> Vec<Block> {
-        let mut new_blocks = vec![];
-        let tetris_vec = &mut self.tetris;
+    // remove every full plane at once and lower the blocks above by the number of planes cleared below them
+    // returns how many planes were removed
+    pub fn clear_full_planes(&mut self) -> usize {
+        let planes = self.check_planes();
+        self.collapse_planes(&planes);
+        planes.len()
+    }
+
+    pub fn clear_plane(&mut self, y: i32) {
+        debug_assert!(0 <= y && y < self.size.y);
+        self.collapse_planes(&[y]);
+    }
 
-        for b in tetris_vec[i].blocks.iter_mut() {
-            // if above cleared plane, lower by 1
-            if b.c.y > y {
-                self.grid[Self::coord_to_index(b.c)] = GridBlock::Vacant;
-                b.c.y -= 1;
-                self.grid[Self::coord_to_index(b.c)] = GridBlock::Occupied(i);
-                new_blocks.push(b.clone());
-            } else if b.c.y < y {
-                self.grid[Self::coord_to_index(b.c)] = GridBlock::Vacant;
-                new_blocks.push(b.clone());
-                self.grid[Self::coord_to_index(b.c)] = GridBlock::Occupied(i);
+    // remove the given planes from every resting piece and compact the stack in one pass,
+    // so plane numbers never go stale while clearing
+    fn collapse_planes(&mut self, planes: &[i32]) {
+        if planes.is_empty() {
+            return;
+        }
+        for t in self.tetris.iter_mut() {
+            if t.falling {
+                continue;
+            }
+            for b in &t.blocks {
+                self.grid[self.size.coord_to_index(b.c)] = GridBlock::Vacant;
+            }
+            t.blocks.retain(|b| !planes.contains(&b.c.y));
+            for b in t.blocks.iter_mut() {
+                b.c.y -= planes.iter().filter(|p| **p < b.c.y).count() as i32;
+            }
+        }
+        // occupy after every piece has been vacated, since pieces move into each other's old cells
+        for (i, t) in self.tetris.iter().enumerate() {
+            if t.falling {
+                continue;
+            }
+            for b in &t.blocks {
+                self.grid[self.size.coord_to_index(b.c)] = GridBlock::Occupied(i);
             }
         }
-        new_blocks
     }
 
-    pub fn clear_plane(&mut self, y: i32) {
-        debug_assert!(0 <= y && y < GRID_Y_MAX);
-        // println!("clear function");
-        let start_i = y * GRID_Z_MAX * GRID_X_MAX;
-        let mut modified = vec![];
-        let mut grid = self.grid;
-
-        // go through every gridblock in this plane
-        for (i, g) in grid
-            [(y * GRID_Z_MAX * GRID_X_MAX) as usize..((y + 1) * GRID_Z_MAX * GRID_X_MAX) as usize]
-            .iter_mut()
-            .enumerate()
-        {
-            let coord = Grid::index_to_coord(start_i as usize + i);
-            if let GridBlock::Occupied(tetris_i) = g {
-                // don't split piece if already split
-                if !modified.contains(tetris_i) {
-                    let new_blocks = self.split_piece(*tetris_i, y);
-                    self.tetris[*tetris_i].blocks = new_blocks;
-                    modified.push(*tetris_i);
+    // clear full planes and bring the blocks above down according to the gravity mode
+    // returns how many planes each cascade after the first clear removed, in order, for chain scoring
+    pub fn clear_planes(&mut self, planes: &[i32]) -> Vec<usize> {
+        let mut chains = vec![];
+        match self.gravity {
+            GravityMode::Naive => self.collapse_planes(planes),
+            GravityMode::Sticky => {
+                for p in planes {
+                    self.remove_plane(*p);
+                }
+                while self.settle_blocks() {}
+            }
+            GravityMode::Cascade => {
+                for p in planes {
+                    self.remove_plane(*p);
+                }
+                loop {
+                    while self.settle_blocks() {}
+                    let planes = self.check_planes();
+                    if planes.is_empty() {
+                        break;
+                    }
+                    for p in planes.iter() {
+                        self.remove_plane(*p);
+                    }
+                    chains.push(planes.len());
                 }
             }
         }
+        chains
     }
+
+    // take every block on plane y out of the grid, leaving what is above floating
+    fn remove_plane(&mut self, y: i32) {
+        for t in self.tetris.iter_mut() {
+            for b in t.blocks.iter().filter(|b| b.c.y == y) {
+                self.grid[self.size.coord_to_index(b.c)] = GridBlock::Vacant;
+            }
+            t.blocks.retain(|b| b.c.y != y);
+        }
+    }
+
+    // drop every group of connected resting blocks as far as it can go, lowest group first
+    // returns whether anything moved
+    fn settle_blocks(&mut self) -> bool {
+        // which tetris and block sits in each occupied cell
+        let mut owners = HashMap::new();
+        for (t, tetris) in self.tetris.iter().enumerate() {
+            if tetris.falling {
+                continue;
+            }
+            for (b, block) in tetris.blocks.iter().enumerate() {
+                owners.insert(self.size.coord_to_index(block.c), (t, b));
+            }
+        }
+
+        // flood fill face neighbors into groups
+        let mut cells: Vec<usize> = owners.keys().copied().collect();
+        cells.sort();
+        let mut group_of = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for start in cells {
+            if group_of.contains_key(&start) {
+                continue;
+            }
+            let g = groups.len();
+            let mut group = vec![start];
+            group_of.insert(start, g);
+            let mut k = 0;
+            while k < group.len() {
+                let c = self.size.index_to_coord(group[k]);
+                for n in NEIGHBORS.iter() {
+                    let nc = c + *n;
+                    if !self.size.contains(nc) {
+                        continue;
+                    }
+                    let ni = self.size.coord_to_index(nc);
+                    if owners.contains_key(&ni) && !group_of.contains_key(&ni) {
+                        group_of.insert(ni, g);
+                        group.push(ni);
+                    }
+                }
+                k += 1;
+            }
+            groups.push(group);
+        }
+        groups.sort_by_key(|g| g.iter().map(|i| self.size.index_to_coord(*i).y).min());
+
+        let mut moved = false;
+        for (g, group) in groups.iter().enumerate() {
+            let coords: Vec<GridCoord> = group.iter().map(|i| self.size.index_to_coord(*i)).collect();
+            let below_free = |d: i32| {
+                coords.iter().all(|c| {
+                    let to = *c + GridOffset::new(0, -d, 0);
+                    if !self.size.contains(to) {
+                        return false;
+                    }
+                    let i = self.size.coord_to_index(to);
+                    self.grid[i].is_vacant() || group_of.get(&i) == Some(&g)
+                })
+            };
+            let mut d = 0;
+            while below_free(d + 1) {
+                d += 1;
+            }
+            if d == 0 {
+                continue;
+            }
+            for c in &coords {
+                self.grid[self.size.coord_to_index(*c)] = GridBlock::Vacant;
+            }
+            for i in group {
+                let (t, b) = owners[i];
+                let block = &mut self.tetris[t].blocks[b];
+                block.c.y -= d;
+                self.grid[self.size.coord_to_index(block.c)] = GridBlock::Occupied(t);
+            }
+            moved = true;
+        }
+        moved
+    }
+
+
     // takes a grid coordinate, returns index in grid struct
-    pub fn coord_to_index(c: GridCoord) -> usize {
-        Self::xyz_to_index(c.x, c.y, c.z)
+    pub fn coord_to_index(&self, c: GridCoord) -> usize {
+        self.size.coord_to_index(c)
     }
 
     // takes index in grid struct, returns grid coordinate
-    pub fn index_to_coord(i: usize) -> GridCoord {
-        let (x, y, z) = Self::index_to_xyz(i);
-        GridCoord::new(x, y, z)
+    pub fn index_to_coord(&self, i: usize) -> GridCoord {
+        self.size.index_to_coord(i)
+    }
+
+    // shapes are laid out for the default grid, so shift them to the top center of this one
+    pub fn spawn_offset(&self, t: &Tetris) -> GridOffset {
+        let d = GridSize::default();
+        let mut offset = GridOffset::new(
+            self.size.x / 2 - d.x / 2,
+            self.size.y - d.y,
+            self.size.z / 2 - d.z / 2,
+        );
+        // keep the piece inside narrow grids
+        for axis in 0..3 {
+            let min = t.blocks.iter().map(|b| b.c[axis]).min().unwrap_or(0) + offset[axis];
+            let max = t.blocks.iter().map(|b| b.c[axis]).max().unwrap_or(0) + offset[axis];
+            if min < 0 {
+                offset[axis] -= min;
+            } else if max >= self.size[axis] {
+                offset[axis] -= max - self.size[axis] + 1;
+            }
+        }
+        offset
     }
 
     // spawn new tetris piece
     pub fn add_tetris(&mut self) {
-        let tetris = Tetris::gen_random_tetris();
+        let shape = self.next_shape();
+        let mut tetris = self.make_tetris(shape);
+        tetris.translate(self.spawn_offset(&tetris));
         // println!("add_tetris function");
         let i = self.tetris.len();
         for b in &tetris.blocks {
             // println!(" coord: {:?}", b.c);
-            // println!("gridblock: {:?}", self.grid[Self::coord_to_index(b.c)]);
-            if (!(self.grid[Self::coord_to_index(b.c)].is_vacant())
-                && self.grid[Self::coord_to_index(b.c)] != GridBlock::Occupied(i))
+            // println!("gridblock: {:?}", self.grid[self.size.coord_to_index(b.c)]);
+            if (!(self.grid[self.size.coord_to_index(b.c)].is_vacant())
+                && self.grid[self.size.coord_to_index(b.c)] != GridBlock::Occupied(i))
             {
                 self.end_game();
                 return;
             }
-            // debug_assert_eq!(self.grid[Self::coord_to_index(b.c)], GridBlock::Vacant);
+            // debug_assert_eq!(self.grid[self.size.coord_to_index(b.c)], GridBlock::Vacant);
             // println!("coord: {:?}", b.c);
-            // println!("gridblock: {:?}", self.grid[Self::coord_to_index(b.c)]);
-            self.grid[Self::coord_to_index(b.c)] = GridBlock::Occupied(i);
+            // println!("gridblock: {:?}", self.grid[self.size.coord_to_index(b.c)]);
+            self.grid[self.size.coord_to_index(b.c)] = GridBlock::Occupied(i);
         }
         self.tetris.push(tetris);
         self.current = i;
+        self.hold_used = false;
+    }
+
+    // swap the falling piece with the held one, or with the next piece if nothing is held
+    // the swapped in piece starts over at the spawn point
+    // returns false if hold was already used this piece or the spawn point is blocked
+    pub fn hold_current(&mut self) -> bool {
+        let i = self.current;
+        if self.end || self.hold_used || !self.tetris[i].falling {
+            return false;
+        }
+        self.fill_queue();
+        let shape = self.held.unwrap_or(self.queue[0]);
+        let mut tetris = self.make_tetris(shape);
+        tetris.translate(self.spawn_offset(&tetris));
+        if !tetris.blocks.iter().all(|b| self.cell_free(b.c, i)) {
+            return false;
+        }
+        if self.held.is_none() {
+            self.next_shape();
+        }
+
+        self.held = Some(self.tetris[i].shape);
+        self.hold_used = true;
+        self.place_tetris(i, tetris.blocks.clone());
+        self.tetris[i] = tetris;
+        true
+    }
+
+    // raise the stack by count planes and fill the bottom with garbage, leaving the cells in holes open
+    // plane k uses holes[k % holes.len()], so a single hole makes a straight well
+    // holes outside the grid wrap around, the sender picks them in its own grid which may be bigger
+    // returns false and ends the game if the stack is pushed out the top
+    pub fn insert_garbage(&mut self, count: usize, holes: &[[i32; 2]]) -> bool {
+        if count == 0 || self.end {
+            return !self.end;
+        }
+        // anything taller than the grid tops out anyway, clamp so a huge count cannot wrap negative
+        let n = count.min(self.size.y as usize) as i32;
+        let top = self
+            .tetris
+            .iter()
+            .filter(|t| !t.falling)
+            .flat_map(|t| t.blocks.iter())
+            .map(|b| b.c.y)
+            .max();
+        if top.map_or(false, |y| y + n >= self.size.y) || n >= self.size.y {
+            self.end_game();
+            return false;
+        }
+
+        // lift everything that has landed, vacating first since blocks move into each other's cells
+        for t in self.tetris.iter().filter(|t| !t.falling) {
+            for b in &t.blocks {
+                self.grid[self.size.coord_to_index(b.c)] = GridBlock::Vacant;
+            }
+        }
+        for (i, t) in self.tetris.iter_mut().enumerate() {
+            if t.falling {
+                continue;
+            }
+            for b in t.blocks.iter_mut() {
+                b.c.y += n;
+                self.grid[self.size.coord_to_index(b.c)] = GridBlock::Occupied(i);
+            }
+        }
+
+        let i = self.tetris.len();
+        let mut blocks = vec![];
+        for y in 0..n {
+            let hole = holes
+                .get(y as usize % holes.len().max(1))
+                .map(|h| [h[0].rem_euclid(self.size.x), h[1].rem_euclid(self.size.z)]);
+            for x in 0..self.size.x {
+                for z in 0..self.size.z {
+                    if hole != Some([x, z]) {
+                        let c = GridCoord::new(x, y, z);
+                        self.grid[self.size.coord_to_index(c)] = GridBlock::Occupied(i);
+                        blocks.push(Block { c, color: TetrisColor::Mix });
+                    }
+                }
+            }
+        }
+        self.tetris.push(Tetris::resting(blocks));
+
+        // the falling piece may now be inside the stack, push it up out of the way
+        let curr = self.current;
+        if self.tetris[curr].falling {
+            let inside = self.tetris[curr]
+                .blocks
+                .iter()
+                .any(|b| self.grid[self.size.coord_to_index(b.c)] != GridBlock::Occupied(curr));
+            if inside {
+                match (1..=n).find(|d| self.tetris_fits(curr, GridOffset::new(0, *d, 0))) {
+                    Some(d) => {
+                        // only vacate the old cells the stack didn't take over
+                        for b in &self.tetris[curr].blocks {
+                            let k = self.size.coord_to_index(b.c);
+                            if self.grid[k] == GridBlock::Occupied(curr) {
+                                self.grid[k] = GridBlock::Vacant;
+                            }
+                        }
+                        for b in self.tetris[curr].blocks.iter_mut() {
+                            b.c.y += d;
+                            self.grid[self.size.coord_to_index(b.c)] = GridBlock::Occupied(curr);
+                        }
+                        self.tetris[curr].pivot.y += d as f32;
+                    }
+                    None => {
+                        self.end_game();
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // put every pending garbage attack into the grid
+    pub fn insert_pending_garbage(&mut self) {
+        for g in std::mem::take(&mut self.pending_garbage) {
+            if !self.insert_garbage(g.planes, &[g.hole]) {
+                return;
+            }
+        }
+    }
+
+    // use an outgoing attack to cancel pending garbage first, returns the planes left to send
+    pub fn cancel_garbage(&mut self, mut planes: usize) -> usize {
+        while planes > 0 && !self.pending_garbage.is_empty() {
+            let g = &mut self.pending_garbage[0];
+            let cancel = planes.min(g.planes);
+            g.planes -= cancel;
+            planes -= cancel;
+            if g.planes == 0 {
+                self.pending_garbage.remove(0);
+            }
+        }
+        planes
+    }
+
+    pub fn pending_garbage_planes(&self) -> usize {
+        self.pending_garbage.iter().map(|g| g.planes).sum()
+    }
+
+    // start keeping placements for undo, from the current state
+    pub fn enable_history(&mut self) {
+        let mut history = History::default();
+        history.undo.push_back(self.clone());
+        self.history = Some(history);
+    }
+
+    // remember the state as it is now, call when a new piece spawns
+    pub fn record_history(&mut self) {
+        // taken out while cloning, so grids without history never pay for the copy
+        if let Some(mut history) = self.history.take() {
+            history.undo.push_back(self.clone());
+            if history.undo.len() > HISTORY_LEN {
+                history.undo.pop_front();
+            }
+            history.redo.clear();
+            self.history = Some(history);
+        }
+    }
+
+    // go back to when the previous piece spawned, returns false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let mut history = match self.history.take() {
+            Some(h) => h,
+            None => return false,
+        };
+        if history.undo.len() < 2 {
+            self.history = Some(history);
+            return false;
+        }
+        history.redo.push(history.undo.pop_back().unwrap());
+        *self = history.undo.back().unwrap().clone();
+        self.history = Some(history);
+        true
+    }
+
+    // put back a placement taken away by undo, returns false if there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let mut history = match self.history.take() {
+            Some(h) => h,
+            None => return false,
+        };
+        let next = match history.redo.pop() {
+            Some(g) => g,
+            None => {
+                self.history = Some(history);
+                return false;
+            }
+        };
+        *self = next.clone();
+        history.undo.push_back(next);
+        self.history = Some(history);
+        true
     }
 
     // change all tetris colors and stop spawning new ones when game over
     pub fn end_game(&mut self) {
-        println!("You died. GG.");
+        if self.won {
+            println!("Well played!");
+        } else {
+            println!("You died. GG.");
+        }
+        println!("{}", self.mode.end_stats(&self.score, self.frames, self.won));
         for t in self.tetris.iter_mut() {
             for b in t.blocks.iter_mut() {
                 b.color = TetrisColor::Mix;
@@ -399,8 +943,25 @@ impl Grid {
         self.end = true;
     }
 
+    // run once per frame, advances the clock for timed modes
+    pub fn tick(&mut self) {
+        if self.end {
+            return;
+        }
+        self.frames += 1;
+        self.check_goal();
+    }
+
+    // end the game if the mode's goal has been met
+    pub fn check_goal(&mut self) {
+        if !self.end && self.mode.won(&self.score, self.frames) {
+            self.won = true;
+            self.end_game();
+        }
+    }
+
     pub fn tetris_at_xyz(&mut self, x: i32, y: i32, z: i32) -> Option<&mut Tetris> {
-        let block = self.grid[Self::xyz_to_index(x, y, z)];
+        let block = self.grid[self.size.xyz_to_index(x, y, z)];
         match block {
             GridBlock::Occupied(i) => Some(&mut self.tetris[i]),
             GridBlock::Vacant => None,
@@ -412,186 +973,350 @@ impl Grid {
         self.tetris_at_xyz(x, y, z)
     }
 
-    // drop tetris by one grid spot
-    pub fn lower_tetris(&mut self, i: usize) {
+    // drop tetris by one grid spot if nothing is below
+    // returns whether the piece moved, resting pieces are locked by update_lock
+    pub fn lower_tetris(&mut self, i: usize) -> bool {
+        self.shift_tetris(i, GridOffset::new(0, -1, 0))
+    }
+
+    // run once per frame, locks the piece once it has rested for lock_delay frames
+    pub fn update_lock(&mut self, i: usize) {
+        if !self.tetris[i].falling {
+            return;
+        }
+        if self.tetris_fits(i, GridOffset::new(0, -1, 0)) {
+            self.tetris[i].lock_timer = 0;
+            return;
+        }
+        self.tetris[i].lock_timer += 1;
+        if self.tetris[i].lock_timer >= self.lock_delay {
+            self.tetris[i].falling = false;
+        }
+    }
+
+    // a successful move or rotation restarts the lock timer of a resting piece, a limited number of times
+    fn reset_lock(&mut self, i: usize) {
         let t = &mut self.tetris[i];
-        // println!("lower_tetris function");
-
-        // check if all spaces below are vacant or self and not floor
-        for block in t.blocks.iter_mut() {
-            let check = GridCoord::new(block.c.x, block.c.y - 1, block.c.z);
-            // println!("check: {:?}", check);
-            // println!(
-            //     "if {:?}",
-            //     check.y == -1
-            //         || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-            //             && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-            // );
-            if check.y == -1
-                || ((!(self.grid[Self::coord_to_index(check)].is_vacant())
-                && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i)))
-            {
-                // println!("stop falling at {:?}", block.c);
-                // println!("check y: {:?}", check.y);
-                // println!(
-                //     "check not vacant: {:?}",
-                //     check.y == -1
-                //         || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                //             && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                // );
-                // if check.y != -1 {
-                //     println!(
-                //         "occupied by: {:?}, current: {:?}",
-                //         self.grid[Self::coord_to_index(check)],
-                //         i
-                //     );
-                // }
-                t.falling = false;
-                return;
-            };
+        if t.lock_timer > 0 && t.lock_resets < self.max_lock_resets {
+            t.lock_timer = 0;
+            t.lock_resets += 1;
         }
+    }
 
-        // lower tetris by 1
-        for block in t.blocks.iter_mut() {
-            self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-            block.c.y -= 1;
-            self.grid[Self::coord_to_index(block.c)] = GridBlock::Occupied(i);
+    // true if c is inside the grid and either vacant or part of tetris i
+    pub fn cell_free(&self, c: GridCoord, i: usize) -> bool {
+        if !self.size.contains(c) {
+            return false;
         }
+        let g = self.grid[self.size.coord_to_index(c)];
+        g.is_vacant() || g == GridBlock::Occupied(i)
     }
 
-    pub fn probe_lowest(&self, x: i32, y: i32, z: i32, i: usize) {}
+    // rotate tetris 90 degrees around its pivot, kicking it to the first free offset in its kick table
+    // returns false and leaves the piece alone if every kick is blocked
+    pub fn rotate_tetris(&mut self, i: usize, axis: Axis, dir: RotateDir) -> bool {
+        if !self.tetris[i].falling {
+            return false;
+        }
+        let rotated = self.tetris[i].rotated(axis, dir);
+        let kicks = self.pieces.pieces[self.tetris[i].shape].kicks;
+        for kick in kick_table(kicks, axis, dir) {
+            if rotated.iter().all(|b| self.cell_free(b.c + kick, i)) {
+                self.place_tetris(i, rotated.iter().map(|b| Block { c: b.c + kick, color: b.color }).collect());
+                self.tetris[i].pivot += kick.cast::<f32>().unwrap();
+                self.reset_lock(i);
+                return true;
+            }
+        }
+        false
+    }
 
-    // drop to lowest possible position
-    pub fn drop_tetris(&mut self, i: usize) {
-        let t = &mut self.tetris[i];
+    // replace the blocks of tetris i, keeping the grid in sync
+    fn place_tetris(&mut self, i: usize, blocks: Vec<Block>) {
+        // vacate every old cell before occupying the new ones, since they can overlap
+        for b in &self.tetris[i].blocks {
+            self.grid[self.size.coord_to_index(b.c)] = GridBlock::Vacant;
+        }
+        for b in &blocks {
+            self.grid[self.size.coord_to_index(b.c)] = GridBlock::Occupied(i);
+        }
+        self.tetris[i].blocks = blocks;
+    }
 
-        let mut check_t = t.clone();
-
-        while check_t.falling {
-            for b in check_t.blocks.iter_mut() {
-                let check = GridCoord::new(b.c.x, b.c.y - 1, b.c.z);
-                // check that space below is vacant and not self and not below grid
-                if check.y == -1
-                    || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                    && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                {
-                    check_t.falling = false;
-                } else {
-                    // keep lowering until you can't
-                    b.c.y -= 1;
-                };
-                // debug_assert!(Self::tetris_at_xyz(&self,block.c.x, block.c.y-1, block.c.z)==None);
-                // self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-                // block.c.y -= 1;
-                // block.falling = false;
-            }
+    // replace tetris i with t, keeping the grid in sync
+    pub fn set_tetris(&mut self, i: usize, t: Tetris) {
+        self.place_tetris(i, t.blocks.clone());
+        self.tetris[i] = t;
+    }
+
+    // true if every block of tetris i would be free after moving it by offset
+    pub fn tetris_fits(&self, i: usize, offset: GridOffset) -> bool {
+        self.tetris[i].blocks.iter().all(|b| self.cell_free(b.c + offset, i))
+    }
+
+    // move the whole piece by offset if nothing is in the way
+    pub fn shift_tetris(&mut self, i: usize, offset: GridOffset) -> bool {
+        if !self.tetris_fits(i, offset) {
+            return false;
         }
+        let blocks = self.tetris[i].blocks.iter().map(|b| Block { c: b.c + offset, color: b.color }).collect();
+        self.place_tetris(i, blocks);
+        self.tetris[i].pivot += offset.cast::<f32>().unwrap();
+        true
+    }
 
-        for b in &check_t.blocks {
-            for a in t.blocks.iter_mut() {
-                self.grid[Self::coord_to_index(a.c)] = GridBlock::Vacant;
-                a.c = b.c; // change to lowest, final position
-                self.grid[Self::coord_to_index(a.c)] = GridBlock::Occupied(i);
-            }
+    // how far tetris i can fall before landing on the floor or the stack
+    pub fn drop_distance(&self, i: usize) -> i32 {
+        let mut d = 0;
+        while d < self.size.y && self.tetris_fits(i, GridOffset::new(0, -(d + 1), 0)) {
+            d += 1;
+        }
+        d
+    }
+
+    // blocks of tetris i where it would land if dropped now, without moving it
+    pub fn landing_blocks(&self, i: usize) -> Vec<Block> {
+        let d = self.drop_distance(i);
+        self.tetris[i]
+            .blocks
+            .iter()
+            .map(|b| Block { c: b.c + GridOffset::new(0, -d, 0), color: b.color })
+            .collect()
+    }
+
+    // drop to lowest possible position and land there
+    // returns the number of planes dropped
+    pub fn drop_tetris(&mut self, i: usize) -> i32 {
+        if !self.tetris[i].falling {
+            return 0;
         }
-        t.falling = false;
+        let d = self.drop_distance(i);
+        self.shift_tetris(i, GridOffset::new(0, -d, 0));
+        self.tetris[i].falling = false;
+        d
     }
 
     // move tetris piece one grid spot in XZ plane
     // 0 left, 1 right, 2 up, 3 down
     pub fn move_xz(&mut self, i: usize, d: usize) {
-        let t = &mut self.tetris[i];
-        // println!("move_xz function");
         // don't move if will be out of grid
-        if t.falling
-        {
-            match d {
-                // Left
-                0 => {
-                    // println!("left");
-                    // check move won't crash into any occupied spaces
-                    for block in &t.blocks {
-                        let check = GridCoord::new(block.c.x - 1, block.c.y, block.c.z);
-
-                        if check.x == -1
-                            || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                            && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                        {
-                            return;
-                        };
-                    }
+        if !self.tetris[i].falling {
+            return;
+        }
+        let offset = match d {
+            0 => GridOffset::new(-1, 0, 0),
+            1 => GridOffset::new(1, 0, 0),
+            2 => GridOffset::new(0, 0, 1),
+            3 => GridOffset::new(0, 0, -1),
+            _ => return,
+        };
+        if self.shift_tetris(i, offset) {
+            self.reset_lock(i);
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    // move each block in tetris left 1
-                    for block in t.blocks.iter_mut() {
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-                        block.c.x -= 1;
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Occupied(i);
-                    }
-                }
-                // Right
-                1 => {
-                    // check move won't crash into any occupied spaces
-                    for block in &t.blocks {
-                        let check = GridCoord::new(block.c.x + 1, block.c.y, block.c.z);
-                        if check.x == 8
-                            || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                            && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                        {
-                            return;
-                        };
-                    }
+    // a default grid whose falling piece is the named shape, pivot at pivot, optionally turned first
+    fn grid_with(name: &str, pivot: GridCoord, turn: Option<(Axis, RotateDir)>) -> Grid {
+        let mut g = Grid::new(cgmath::Vector3::new(0, 0, 0), GridSize::default(), 1);
+        let shape = g.pieces.pieces.iter().position(|p| p.name == name).unwrap();
+        let mut t = g.make_tetris(shape);
+        if let Some((axis, dir)) = turn {
+            t.blocks = t.rotated(axis, dir);
+        }
+        let at = GridCoord::new(t.pivot.x as i32, t.pivot.y as i32, t.pivot.z as i32);
+        t.translate(pivot - at);
+        let i = g.current;
+        g.set_tetris(i, t);
+        g
+    }
 
+    // rotate the falling piece and check it landed at the plain rotation moved by kick, or did not move at all
+    fn assert_rotation(g: &mut Grid, axis: Axis, dir: RotateDir, kick: Option<GridOffset>) {
+        let i = g.current;
+        let before = g.tetris[i].clone();
+        let moved = g.rotate_tetris(i, axis, dir);
+        match kick {
+            Some(kick) => {
+                assert!(moved, "rotation was rejected");
+                let expected: Vec<GridCoord> = before.rotated(axis, dir).iter().map(|b| b.c + kick).collect();
+                let blocks: Vec<GridCoord> = g.tetris[i].blocks.iter().map(|b| b.c).collect();
+                assert_eq!(blocks, expected);
+                assert_eq!(g.tetris[i].pivot, before.pivot + kick.cast::<f32>().unwrap());
+            }
+            None => {
+                assert!(!moved, "rotation should have been rejected");
+                assert_eq!(g.tetris[i], before);
+            }
+        }
+        for b in g.tetris[i].blocks.iter() {
+            assert_eq!(g.grid[g.coord_to_index(b.c)], GridBlock::Occupied(i));
+        }
+        assert_eq!(g.grid.iter().filter(|c| **c == GridBlock::Occupied(i)).count(), g.tetris[i].blocks.len());
+    }
 
-                    // move each block in tetris left 1
-                    for block in t.blocks.iter_mut() {
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-                        block.c.x += 1;
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Occupied(i);
-                    }
-                }
-                // Up
-                2 => {
-                    // check move won't crash into any occupied spaces
-                    for block in &t.blocks {
-                        let check = GridCoord::new(block.c.x, block.c.y, block.c.z + 1);
-                        if check.z == 8
-                            || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                            && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                        {
-                            return;
-                        };
-                    }
+    // a grid of the given size with no pieces in it
+    fn empty_grid(size: GridSize) -> Grid {
+        let mut g = Grid::new(cgmath::Vector3::new(0, 0, 0), GridSize::default(), 1);
+        g.size = size;
+        g.grid = vec![GridBlock::Vacant; size.len()];
+        g.tetris.clear();
+        g.current = 0;
+        g
+    }
 
-                    // move each block in tetris left 1
-                    for block in t.blocks.iter_mut() {
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-                        block.c.z += 1;
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Occupied(i);
-                    }
-                }
-                // Down
-                3 => {
-                    // check move won't crash into any occupied spaces
-                    for block in &t.blocks {
-                        let check = GridCoord::new(block.c.x, block.c.y, block.c.z - 1);
-                        if check.z == -1
-                            || (!(self.grid[Self::coord_to_index(check)].is_vacant())
-                            && self.grid[Self::coord_to_index(check)] != GridBlock::Occupied(i))
-                        {
-                            return;
-                        };
-                    }
+    // add a resting piece made of the given cells, returning its index
+    fn add_resting(g: &mut Grid, cells: &[[i32; 3]]) -> usize {
+        let i = g.tetris.len();
+        let blocks: Vec<Block> = cells
+            .iter()
+            .map(|c| Block { c: GridCoord::new(c[0], c[1], c[2]), color: TetrisColor::Mix })
+            .collect();
+        for b in blocks.iter() {
+            let k = g.coord_to_index(b.c);
+            assert!(g.grid[k].is_vacant());
+            g.grid[k] = GridBlock::Occupied(i);
+        }
+        g.tetris.push(Tetris::resting(blocks));
+        i
+    }
 
-                    // move each block in tetris left 1
-                    for block in t.blocks.iter_mut() {
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Vacant;
-                        block.c.z -= 1;
-                        self.grid[Self::coord_to_index(block.c)] = GridBlock::Occupied(i);
-                    }
+    // a resting piece filling plane y except for the cells in holes
+    fn add_plane(g: &mut Grid, y: i32, holes: &[[i32; 2]]) -> usize {
+        let mut cells = vec![];
+        for x in 0..g.size.x {
+            for z in 0..g.size.z {
+                if !holes.contains(&[x, z]) {
+                    cells.push([x, y, z]);
                 }
-                _ => (),
             }
         }
+        add_resting(g, &cells)
+    }
+
+    fn ys(g: &Grid, i: usize) -> Vec<i32> {
+        g.tetris[i].blocks.iter().map(|b| b.c.y).collect()
+    }
+
+    // every cell of grid points at the one piece with a block there
+    fn assert_synced(g: &Grid) {
+        let mut expected = vec![GridBlock::Vacant; g.size.len()];
+        for (i, t) in g.tetris.iter().enumerate() {
+            for b in t.blocks.iter() {
+                assert!(g.size.contains(b.c), "block {:?} left the grid", b.c);
+                let k = g.coord_to_index(b.c);
+                assert!(expected[k].is_vacant(), "two blocks at {:?}", b.c);
+                expected[k] = GridBlock::Occupied(i);
+            }
+        }
+        assert!(g.grid == expected, "grid does not match tetris");
+    }
+
+    #[test]
+    fn clears_planes_apart() {
+        let mut g = empty_grid(GridSize::new(2, 6, 2));
+        let bottom = add_plane(&mut g, 0, &[]);
+        let between = add_resting(&mut g, &[[0, 1, 0]]);
+        let middle = add_plane(&mut g, 2, &[]);
+        let top = add_resting(&mut g, &[[1, 3, 1], [1, 4, 1]]);
+        assert_eq!(g.clear_full_planes(), 2);
+        assert!(g.tetris[bottom].blocks.is_empty());
+        assert!(g.tetris[middle].blocks.is_empty());
+        assert_eq!(ys(&g, between), vec![0]);
+        assert_eq!(ys(&g, top), vec![1, 2]);
+        assert_synced(&g);
+        assert_eq!(g.clear_full_planes(), 0);
+    }
+
+    #[test]
+    fn clears_adjacent_planes() {
+        let mut g = empty_grid(GridSize::new(2, 6, 2));
+        let below = add_plane(&mut g, 0, &[[1, 1]]);
+        add_plane(&mut g, 1, &[]);
+        add_plane(&mut g, 2, &[]);
+        let above = add_resting(&mut g, &[[0, 3, 0], [0, 4, 0], [1, 4, 0]]);
+        assert_eq!(g.clear_full_planes(), 2);
+        assert_eq!(ys(&g, below), vec![0, 0, 0]);
+        assert_eq!(ys(&g, above), vec![1, 2, 2]);
+        assert_synced(&g);
+    }
+
+    #[test]
+    fn clears_through_a_piece() {
+        let mut g = empty_grid(GridSize::new(2, 6, 2));
+        // a column from the floor up through both cleared planes
+        let column = add_resting(&mut g, &[[0, 0, 0], [0, 1, 0], [0, 2, 0], [0, 3, 0], [0, 4, 0]]);
+        add_plane(&mut g, 1, &[[0, 0]]);
+        add_plane(&mut g, 3, &[[0, 0]]);
+        let side = add_resting(&mut g, &[[1, 2, 1], [1, 4, 1]]);
+        assert_eq!(g.clear_full_planes(), 2);
+        assert_eq!(ys(&g, column), vec![0, 1, 2]);
+        assert_eq!(ys(&g, side), vec![1, 2]);
+        assert_synced(&g);
+    }
+
+    #[test]
+    fn rotation_kicks_off_left_wall() {
+        let mut g = grid_with("l", GridCoord::new(0, 8, 4), None);
+        assert_rotation(&mut g, Axis::Z, RotateDir::CounterClockwise, Some(GridOffset::new(1, 0, 0)));
+    }
+
+    #[test]
+    fn line_needs_two_cells_off_right_wall() {
+        let x = GridSize::default().x - 1;
+        let mut g = grid_with("line", GridCoord::new(x, 8, 4), None);
+        assert_rotation(&mut g, Axis::Z, RotateDir::Clockwise, Some(GridOffset::new(-2, 0, 0)));
+    }
+
+    #[test]
+    fn rotation_kicks_off_front_and_back_walls() {
+        let mut g = grid_with("l", GridCoord::new(4, 8, 0), None);
+        assert_rotation(&mut g, Axis::X, RotateDir::CounterClockwise, Some(GridOffset::new(0, 0, 1)));
+        let z = GridSize::default().z - 1;
+        let mut g = grid_with("l", GridCoord::new(4, 8, z), None);
+        assert_rotation(&mut g, Axis::X, RotateDir::Clockwise, Some(GridOffset::new(0, 0, -1)));
+    }
+
+    #[test]
+    fn rotation_kicks_off_floor() {
+        let mut g = grid_with("t", GridCoord::new(4, 0, 4), None);
+        assert_rotation(&mut g, Axis::Z, RotateDir::CounterClockwise, Some(GridOffset::new(0, 1, 0)));
+    }
+
+    #[test]
+    fn line_kicks_down_from_ceiling() {
+        let y = GridSize::default().y - 1;
+        let flat = Some((Axis::Z, RotateDir::CounterClockwise));
+        let mut g = grid_with("line", GridCoord::new(4, y, 4), flat);
+        assert_rotation(&mut g, Axis::Z, RotateDir::Clockwise, Some(GridOffset::new(0, -2, 0)));
+
+        // with the only kick that fits blocked the rotation is rejected
+        let mut g = grid_with("line", GridCoord::new(4, y, 4), flat);
+        add_resting(&mut g, &[[4, y - 3, 4]]);
+        assert_rotation(&mut g, Axis::Z, RotateDir::Clockwise, None);
+    }
+
+    #[test]
+    fn garbage_hole_wraps_into_grid() {
+        // an opponent with a 12 wide grid can pick a hole this 8x8 grid does not have
+        let mut g = Grid::new(cgmath::Vector3::new(0, 0, 0), GridSize::default(), 1);
+        assert!(g.insert_garbage(2, &[[10, -1]]));
+        let garbage = g.tetris.last().unwrap();
+        assert_eq!(garbage.blocks.len(), 2 * (8 * 8 - 1));
+        for y in 0..2 {
+            assert_eq!(g.grid[g.coord_to_index(GridCoord::new(2, y, 7))], GridBlock::Vacant);
+        }
+    }
+    #[test]
+    fn huge_garbage_tops_out() {
+        let mut g = Grid::new(cgmath::Vector3::new(0, 0, 0), GridSize::default(), 1);
+        assert!(!g.insert_garbage(usize::MAX, &[[0, 0]]));
+        assert!(g.end);
+        let mut g = Grid::new(cgmath::Vector3::new(0, 0, 0), GridSize::default(), 1);
+        assert!(!g.insert_garbage(1 << 32, &[[0, 0]]));
     }
 }
@@ -9,6 +9,7 @@ pub mod audio;
 pub mod camera;
 pub mod collision;
 pub mod events;
+pub mod generator;
 pub mod geom;
 pub mod model;
 pub mod texture;
@@ -21,8 +22,16 @@ pub mod camera_control;
 pub mod grid;
 pub mod lights;
 pub mod serialization;
+pub mod binary;
 pub mod save;
+pub mod score;
+pub mod mode;
+pub mod sim;
+pub mod ai;
+pub mod replay;
+pub mod puzzle;
 pub mod network;
+pub mod pieces;
 
 pub const DT: f32 = 1.0 / 60.0;
 
@@ -0,0 +1,133 @@
+use crate::score::Score;
+use crate::DT;
+use serde::{Deserialize, Serialize};
+
+// planes to clear to win sprint
+pub const SPRINT_PLANES: u32 = 40;
+// length of an ultra game, 3 minutes of updates, rounded since 1/60 is not exact in f32
+pub const ULTRA_FRAMES: usize = (180.0 / DT + 0.5) as usize;
+// planes to clear to finish marathon, level 15
+pub const MARATHON_PLANES: u32 = 150;
+// zen clears the bottom plane for the player after this many pieces without a clear
+pub const ZEN_ASSIST_PIECES: u32 = 15;
+
+// rules for how a game is won or lost
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GameMode {
+    // clear planes as the levels speed up, until the stack reaches the top or MARATHON_PLANES are cleared
+    Marathon,
+    // clear SPRINT_PLANES as fast as possible
+    Sprint,
+    // score as much as possible in ULTRA_FRAMES
+    Ultra,
+    // no goal, and the bottom plane gets cleared every so often
+    Zen,
+    // no goal, and placements can be undone, never in multiplayer
+    Practice,
+    // a fixed stack and piece list with a goal, checked by the puzzle itself
+    Puzzle,
+}
+
+impl Default for GameMode {
+    fn default() -> Self {
+        GameMode::Marathon
+    }
+}
+
+impl GameMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Marathon => "marathon",
+            GameMode::Sprint => "sprint",
+            GameMode::Ultra => "ultra",
+            GameMode::Zen => "zen",
+            GameMode::Practice => "practice",
+            GameMode::Puzzle => "puzzle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "marathon" => Some(GameMode::Marathon),
+            "sprint" => Some(GameMode::Sprint),
+            "ultra" => Some(GameMode::Ultra),
+            "zen" => Some(GameMode::Zen),
+            "practice" => Some(GameMode::Practice),
+            "puzzle" => Some(GameMode::Puzzle),
+            _ => None,
+        }
+    }
+
+    // true once the goal is met and the game should stop
+    pub fn won(&self, score: &Score, frames: usize) -> bool {
+        match self {
+            GameMode::Marathon => score.planes_cleared >= MARATHON_PLANES,
+            GameMode::Sprint => score.planes_cleared >= SPRINT_PLANES,
+            GameMode::Ultra => frames >= ULTRA_FRAMES,
+            GameMode::Zen | GameMode::Practice | GameMode::Puzzle => false,
+        }
+    }
+
+    // plane the mode clears for the player after a piece lands without clearing anything, if any
+    pub fn assist_plane(&self, score: &Score) -> Option<i32> {
+        match self {
+            GameMode::Zen if score.since_clear >= ZEN_ASSIST_PIECES => Some(0),
+            _ => None,
+        }
+    }
+
+    // summary printed when the game ends
+    pub fn end_stats(&self, score: &Score, frames: usize, won: bool) -> String {
+        let time = format_time(frames);
+        let result = match (self, won) {
+            (GameMode::Sprint, true) => format!("Sprint cleared in {}", time),
+            (GameMode::Sprint, false) => format!(
+                "Sprint failed with {} of {} planes in {}",
+                score.planes_cleared, SPRINT_PLANES, time
+            ),
+            (GameMode::Ultra, true) => format!("Time's up! Final score: {}", score.points),
+            (GameMode::Marathon, true) => format!("Marathon complete in {}", time),
+            (GameMode::Puzzle, true) => format!("Puzzle solved in {}", time),
+            (GameMode::Puzzle, false) => "Puzzle failed".to_string(),
+            _ => format!("{} over after {}", self.name(), time),
+        };
+        format!(
+            "{}\nScore: {} Level: {} Planes: {} Pieces: {} Best combo: {} Best chain: {}",
+            result,
+            score.points,
+            score.level,
+            score.planes_cleared,
+            score.pieces_placed,
+            score.max_combo,
+            score.max_chain
+        )
+    }
+}
+
+// m:ss.cc from a number of updates
+pub fn format_time(frames: usize) -> String {
+    let centis = (frames as f32 * DT * 100.0).round() as usize;
+    format!("{}:{:02}.{:02}", centis / 6000, centis / 100 % 60, centis % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zen_assists_after_pieces_without_a_clear() {
+        let mut score = Score::new();
+        // a clear partway through restarts the count, so the 15th piece is not enough
+        for planes in [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0].iter() {
+            score.lock_piece(*planes);
+        }
+        assert_eq!(GameMode::Zen.assist_plane(&score), None);
+        for _ in 0..ZEN_ASSIST_PIECES - 4 {
+            score.lock_piece(0);
+        }
+        assert_eq!(GameMode::Zen.assist_plane(&score), Some(0));
+        assert_eq!(GameMode::Marathon.assist_plane(&score), None);
+        score.assist();
+        assert_eq!(GameMode::Zen.assist_plane(&score), None);
+    }
+}
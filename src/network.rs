@@ -1,18 +1,41 @@
 use std;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
 use std::net::{Shutdown, TcpStream};
 use std::str::FromStr;
 use crate::serialization::SerializableGrid;
-use crate::grid::Grid;
+use crate::grid::{Garbage, Grid};
+use serde::Deserialize;
 
-const BUFSIZE: usize = 4096;
+// what the server sends back for each update
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Response {
+    // servers from before garbage only send everyone else's grid
+    Grids(Vec<SerializableGrid>),
+    Full {
+        // everyone else's grid
+        grids: Vec<SerializableGrid>,
+        // attacks sent to us since the last update
+        #[serde(default)]
+        garbage: Vec<Garbage>,
+    },
+}
 
 pub struct Server {
     pub id: i32,
     sock: Option<TcpStream>,
+    // the read side of sock, buffered so bytes past one response are kept for the next
+    reader: Option<BufReader<TcpStream>>,
+    // the response read so far, responses are one line and can take several frames to arrive
+    line: Vec<u8>,
+    // an update was sent and its response has not fully arrived
     waiting: bool,
     pub connected: bool,
     pub has_other: bool,
+    // attacks not yet sent to the server
+    outgoing: Vec<Garbage>,
+    // attacks received but not yet taken by the game
+    incoming: Vec<Garbage>,
 }
 
 #[allow(dead_code)]
@@ -24,6 +47,10 @@ impl Server {
             waiting: false,
             has_other: false,
             sock: None,
+            reader: None,
+            line: vec![],
+            outgoing: vec![],
+            incoming: vec![],
         }
     }
 
@@ -37,12 +64,11 @@ impl Server {
                 return;
             }
         }
-        let mut buf: [u8; BUFSIZE] = [0; BUFSIZE]; // well memory is cheap
-        stream.read(&mut buf).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut s = String::new();
+        reader.read_line(&mut s).unwrap();
         stream.set_nonblocking(true).unwrap();
-        let s = std::str::from_utf8(&buf).unwrap();
-        let term = s.find("\n").unwrap();
-        let id = s[..term].parse::<i32>().unwrap();
+        let id = s.trim().parse::<i32>().unwrap();
         if id == -1 {
             println!("Server capacity reached. Using offline mode. Please try again later");
             return;
@@ -51,6 +77,7 @@ impl Server {
         self.id = id;
         self.connected = true;
         self.sock = Some(stream);
+        self.reader = Some(reader);
     }
 
     fn disconnect(&mut self) {
@@ -60,49 +87,83 @@ impl Server {
         sock.shutdown(Shutdown::Both).unwrap();
     }
 
+    // send an update unless one is still waiting for its response, then read as much of the response as has arrived
     fn update(&mut self, grid: &SerializableGrid) -> Result<Vec<SerializableGrid>, Box<dyn std::error::Error>> {
-        if !self.connected || self.waiting {
+        if !self.connected {
             return Ok(Vec::<SerializableGrid>::new()); // empty vec
         }
-        let mut sock = self.sock.as_ref().unwrap();
-        let obj = serde_json::json!({
-            "op":"update",
-            "data":grid
-        });
-        let j = serde_json::to_string(&obj).unwrap() + "\n";
-        sock.write(j.as_bytes())?;
-        sock.flush().unwrap();
-        let mut buf: [u8; BUFSIZE] = [0; BUFSIZE];
-        sock.read(&mut buf)?;
-        let s = std::str::from_utf8(&mut buf)?;
-        if let Some(term) = s.find("\n") {
-            let v: Vec<SerializableGrid> = serde_json::from_str(&s[..term])?;
-            // println!("Instance {} Recved from server: {}", self.id, s);
-            if v.len()==0{
-                self.has_other=false;
-            } else {
-                self.has_other=true;
-            }
-            Ok(v)
+        if !self.waiting {
+            let mut sock = self.sock.as_ref().unwrap();
+            let obj = serde_json::json!({
+                "op":"update",
+                "data":grid,
+                "garbage":self.outgoing
+            });
+            let j = serde_json::to_string(&obj).unwrap() + "\n";
+            // big grids can fill the send buffer, so block until the whole update is out
+            sock.set_nonblocking(false)?;
+            sock.write_all(j.as_bytes())?;
+            sock.flush()?;
+            sock.set_nonblocking(true)?;
+            self.outgoing.clear();
+            self.waiting = true;
+        }
+        let reader = self.reader.as_mut().unwrap();
+        match reader.read_until(b'\n', &mut self.line) {
+            Ok(0) => return Err("server closed the connection".into()),
+            Ok(_) => {}
+            // the rest has not arrived yet, keep what we have for the next frame
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Vec::<SerializableGrid>::new()),
+            Err(e) => return Err(e.into()),
+        }
+        if self.line.last() != Some(&b'\n') {
+            return Ok(Vec::<SerializableGrid>::new());
+        }
+        let line = std::mem::take(&mut self.line);
+        self.waiting = false;
+        let (v, garbage) = match serde_json::from_slice(&line)? {
+            Response::Grids(grids) => (grids, vec![]),
+            Response::Full { grids, garbage } => (grids, garbage),
+        };
+        // an attack taller than our grid is not one the opponent can have earned, drop it
+        let height = grid.size[1].max(0) as usize;
+        self.incoming.extend(garbage.into_iter().filter(|g| g.planes <= height));
+        // println!("Instance {} Recved from server: {}", self.id, s);
+        if v.len()==0{
+            self.has_other=false;
         } else {
-            // println!("unexpected error. something went wrong");
-            Ok(Vec::<SerializableGrid>::new())
+            self.has_other=true;
+        }
+        Ok(v)
+    }
 
-            // this is not ok but i can't get rust to throw something sensible
+    // queue an attack for the opponent, sent with the next update
+    pub fn send_garbage(&mut self, garbage: Garbage) {
+        if self.connected {
+            self.outgoing.push(garbage);
         }
     }
 
+    // attacks received from the opponent since the last call
+    pub fn take_garbage(&mut self) -> Vec<Garbage> {
+        std::mem::take(&mut self.incoming)
+    }
+
     pub fn update_grid(&mut self, grid: &Grid) -> Vec<SerializableGrid> {
         let sg = SerializableGrid::from_grid(grid);
         let response = self.update(&sg);
         match response {
-            Ok(v) => {
-                self.waiting = false;
-                return v;
+            Ok(v) => v,
+            // a response that does not parse is dropped, the next update asks again
+            Err(e) if e.is::<serde_json::Error>() => {
+                println!("Bad response from server ({})", e);
+                vec![]
             }
-            _ => {
-                self.waiting = true;
-                return vec![];
+            Err(e) => {
+                println!("Lost connection to server ({}). Using offline mode", e);
+                self.connected = false;
+                self.has_other = false;
+                vec![]
             }
         }
     }
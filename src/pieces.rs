@@ -0,0 +1,113 @@
+use crate::grid::*;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+// the six shapes the game shipped with, never edit this file
+// saves and network updates leave out the classic set and old saves index into it, so it has to stay as it is
+// content/pieces.json starts as a copy and is the one to change
+const CLASSIC: &str = include_str!("classic_pieces.json");
+
+// one shape a piece set can spawn
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PieceDef {
+    pub name: String,
+    pub color: TetrisColor,
+    // block positions relative to spawn
+    pub cells: Vec<[i32; 3]>,
+    // rotation center relative to spawn, all whole or all half numbers so rotated cells stay on the grid
+    pub pivot: [f32; 3],
+    // where the piece appears in the default 8x16x8 grid, moved to fit other sizes
+    pub spawn: [i32; 3],
+    pub kicks: Kicks,
+}
+
+impl PieceDef {
+    // whether the piece is no wider, taller or deeper than a grid of this size, so it can spawn
+    pub fn fits(&self, size: GridSize) -> bool {
+        (0..3).all(|axis| {
+            let min = self.cells.iter().map(|c| c[axis]).min().unwrap_or(0);
+            let max = self.cells.iter().map(|c| c[axis]).max().unwrap_or(0);
+            max - min < size[axis]
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PieceSet {
+    pub name: String,
+    pub pieces: Vec<PieceDef>,
+}
+
+impl PieceSet {
+    // parsed once and shared by every grid that uses it
+    pub fn classic() -> Arc<Self> {
+        static SET: OnceLock<Arc<PieceSet>> = OnceLock::new();
+        SET.get_or_init(|| Arc::new(serde_json::from_str(CLASSIC).expect("built in piece set does not parse")))
+            .clone()
+    }
+
+    // checked for every save and network update, so grids sharing the classic set skip the comparison
+    pub fn is_classic(&self) -> bool {
+        let classic = Self::classic();
+        std::ptr::eq(self, &*classic) || *self == *classic
+    }
+
+    pub fn load<T: AsRef<Path>>(filename: T) -> Result<Self> {
+        let filename = filename.as_ref();
+        let s = std::fs::read_to_string(filename)
+            .with_context(|| format!("cannot read piece set {}", filename.display()))?;
+        let set: PieceSet = serde_json::from_str(&s)
+            .with_context(|| format!("cannot parse piece set {}", filename.display()))?;
+        set.validate()
+            .with_context(|| format!("invalid piece set {}", filename.display()))?;
+        Ok(set)
+    }
+
+    // check every piece can spawn and rotate, reporting all problems at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+        if self.pieces.is_empty() {
+            problems.push("no pieces".to_string());
+        }
+        let size = GridSize::default();
+        let mut names = HashSet::new();
+        for p in self.pieces.iter() {
+            if !names.insert(p.name.as_str()) {
+                problems.push(format!("{}: name used more than once", p.name));
+            }
+            if p.cells.is_empty() {
+                problems.push(format!("{}: no cells", p.name));
+            }
+            let mut cells = HashSet::new();
+            for c in p.cells.iter() {
+                if !cells.insert(*c) {
+                    problems.push(format!("{}: cell {:?} listed more than once", p.name, c));
+                }
+                let at = GridCoord::new(p.spawn[0] + c[0], p.spawn[1] + c[1], p.spawn[2] + c[2]);
+                if !size.contains(at) {
+                    problems.push(format!("{}: cell {:?} spawns outside the grid", p.name, c));
+                }
+            }
+            let halves = p.pivot.iter().filter(|v| v.fract().abs() == 0.5).count();
+            let wholes = p.pivot.iter().filter(|v| v.fract() == 0.0).count();
+            if halves != 3 && wholes != 3 {
+                problems.push(format!(
+                    "{}: pivot {:?} must be all whole or all half numbers",
+                    p.name, p.pivot
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            bail!(problems.join("\n"));
+        }
+        Ok(())
+    }
+
+    // first shape drawn in this color, for saves that only recorded block colors
+    pub fn shape_from_color(&self, color: TetrisColor) -> TetrisShape {
+        self.pieces.iter().position(|p| p.color == color).unwrap_or(0)
+    }
+}
@@ -0,0 +1,188 @@
+use crate::generator::SequenceGenerator;
+use crate::geom::*;
+use crate::grid::*;
+use crate::mode::GameMode;
+use crate::pieces::PieceSet;
+use crate::serialization::{checked_cells, GridProblem};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+// where the puzzles that ship with the game live
+pub const PUZZLE_DIR: &str = "content/puzzles";
+
+// what a puzzle asks for
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PuzzleGoal {
+    // clear this many planes in total
+    ClearPlanes(u32),
+    // end up with every one of these cells filled
+    FillCells(Vec<[i32; 3]>),
+}
+
+// a starting stack, the pieces to place on it and a goal
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Puzzle {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub size: GridSize,
+    // planes from the bottom up, each a list of rows along z, each row a string along x with # for a block
+    pub stack: Vec<Vec<String>>,
+    // names from the piece set, dealt in order
+    pub pieces: Vec<String>,
+    pub goal: PuzzleGoal,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PuzzleStatus {
+    InProgress,
+    Solved,
+    Failed,
+}
+
+impl Puzzle {
+    pub fn load<T: AsRef<Path>>(filename: T, pieces: &PieceSet) -> Result<Self> {
+        let filename = filename.as_ref();
+        let s = std::fs::read_to_string(filename)
+            .with_context(|| format!("cannot read puzzle {}", filename.display()))?;
+        let puzzle: Puzzle = serde_json::from_str(&s)
+            .with_context(|| format!("cannot parse puzzle {}", filename.display()))?;
+        puzzle
+            .validate(pieces)
+            .with_context(|| format!("invalid puzzle {}", filename.display()))?;
+        Ok(puzzle)
+    }
+
+    // every puzzle in a directory, in file name order
+    pub fn load_pack<T: AsRef<Path>>(dir: T, pieces: &PieceSet) -> Result<Vec<Self>> {
+        let dir = dir.as_ref();
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir).with_context(|| format!("cannot read puzzle pack {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        files.iter().map(|f| Self::load(f, pieces)).collect()
+    }
+
+    // cells of the starting stack
+    pub fn stack_cells(&self) -> Vec<GridCoord> {
+        let mut cells = vec![];
+        for (y, plane) in self.stack.iter().enumerate() {
+            for (z, row) in plane.iter().enumerate() {
+                for (x, ch) in row.chars().enumerate() {
+                    if ch == '#' {
+                        cells.push(GridCoord::new(x as i32, y as i32, z as i32));
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    // check the puzzle can be played with this piece set, reporting all problems at once
+    pub fn validate(&self, pieces: &PieceSet) -> Result<()> {
+        let mut problems = vec![];
+        if self.pieces.is_empty() {
+            problems.push("no pieces".to_string());
+        }
+        // the size is read straight from the file, so GridSize::new never checked it
+        let size_ok = checked_cells([self.size.x, self.size.y, self.size.z]).is_some();
+        if !size_ok {
+            problems.push(GridProblem::BadSize([self.size.x, self.size.y, self.size.z]).to_string());
+        }
+        for name in self.pieces.iter() {
+            match pieces.pieces.iter().find(|p| p.name == *name) {
+                Some(p) if size_ok && !p.fits(self.size) => {
+                    problems.push(GridProblem::PieceTooBig(name.clone()).to_string())
+                }
+                Some(_) => {}
+                None => problems.push(format!("no piece named {} in the {} set", name, pieces.name)),
+            }
+        }
+        if self.stack.len() as i32 > self.size.y {
+            problems.push("stack is taller than the grid".to_string());
+        }
+        for (y, plane) in self.stack.iter().enumerate() {
+            if plane.len() as i32 > self.size.z || plane.iter().any(|row| row.chars().count() as i32 > self.size.x) {
+                problems.push(format!("plane {} is bigger than the grid", y));
+            }
+            if plane.iter().any(|row| row.chars().any(|c| c != '#' && c != '.')) {
+                problems.push(format!("plane {} has characters other than # and .", y));
+            }
+        }
+        if size_ok {
+            let plane_len = self.size.plane_len();
+            for y in 0..self.stack.len() as i32 {
+                if self.stack_cells().iter().filter(|c| c.y == y).count() == plane_len {
+                    problems.push(format!("plane {} is already full", y));
+                }
+            }
+        }
+        match &self.goal {
+            PuzzleGoal::ClearPlanes(0) => problems.push("goal clears no planes".to_string()),
+            PuzzleGoal::FillCells(cells) => {
+                for c in cells {
+                    if !self.size.contains(GridCoord::new(c[0], c[1], c[2])) {
+                        problems.push(format!("goal cell {:?} is outside the grid", c));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if !problems.is_empty() {
+            bail!(problems.join("\n"));
+        }
+        Ok(())
+    }
+
+    // a grid with the starting stack in place and the first piece falling
+    pub fn to_grid(&self, origin: cgmath::Vector3<i32>, pieces: Arc<PieceSet>) -> Grid {
+        let shapes = self
+            .pieces
+            .iter()
+            .map(|name| pieces.pieces.iter().position(|p| p.name == *name).unwrap_or(0))
+            .collect();
+        let mut grid = Grid::with_pieces(origin, self.size, 0, pieces, Box::new(SequenceGenerator::new(shapes)));
+        grid.mode = GameMode::Puzzle;
+        let i = grid.tetris.len();
+        let mut blocks = vec![];
+        for c in self.stack_cells() {
+            let k = grid.coord_to_index(c);
+            // a stack built into the spawn point ends the puzzle before it starts
+            if !grid.grid[k].is_vacant() {
+                grid.end_game();
+                return grid;
+            }
+            grid.grid[k] = GridBlock::Occupied(i);
+            blocks.push(Block { c, color: TetrisColor::Mix });
+        }
+        grid.tetris.push(Tetris::resting(blocks));
+        grid
+    }
+
+    // solved once the goal is met, failed if the game ended or every piece was placed without meeting it
+    pub fn status(&self, grid: &Grid) -> PuzzleStatus {
+        let solved = match &self.goal {
+            PuzzleGoal::ClearPlanes(n) => grid.score.planes_cleared >= *n,
+            PuzzleGoal::FillCells(cells) => cells.iter().all(|c| {
+                let c = GridCoord::new(c[0], c[1], c[2]);
+                match grid.grid[grid.coord_to_index(c)] {
+                    GridBlock::Occupied(i) => !grid.tetris[i].falling,
+                    GridBlock::Vacant => false,
+                }
+            }),
+        };
+        if solved {
+            PuzzleStatus::Solved
+        } else if grid.end || grid.score.pieces_placed as usize >= self.pieces.len() {
+            PuzzleStatus::Failed
+        } else {
+            PuzzleStatus::InProgress
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::grid::Grid;
+use crate::save::data_dir;
+use crate::serialization::SerializableGrid;
+use crate::sim::{Input, Simulation};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// a whole game as the state it started from, seed included, and the inputs of every frame
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Replay {
+    pub start: SerializableGrid,
+    // frames recorded so far
+    pub length: usize,
+    // only frames that had input, as (frame, inputs) in frame order, to keep files small
+    pub inputs: Vec<(usize, Vec<Input>)>,
+}
+
+impl Replay {
+    // start recording a game that has not been stepped yet
+    pub fn new(grid: &Grid) -> Self {
+        Self {
+            start: SerializableGrid::from_grid(grid),
+            length: 0,
+            inputs: vec![],
+        }
+    }
+
+    // record the inputs of the next frame
+    pub fn record(&mut self, inputs: &[Input]) {
+        if !inputs.is_empty() {
+            self.inputs.push((self.length, inputs.to_vec()));
+        }
+        self.length += 1;
+    }
+
+    // inputs recorded for frame, empty if there were none
+    pub fn inputs_at(&self, frame: usize) -> &[Input] {
+        match self.inputs.binary_search_by_key(&frame, |(f, _)| *f) {
+            Ok(i) => &self.inputs[i].1,
+            Err(_) => &[],
+        }
+    }
+
+    // the recorded game, ready to step from the first frame
+    pub fn simulation(&self) -> Result<Simulation> {
+        let grid = self.start.to_grid()?;
+        Ok(Simulation::with_script(grid, (0..self.length).map(|f| self.inputs_at(f).to_vec()).collect()))
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, filename: T) -> Result<()> {
+        let filename = filename.as_ref();
+        let s = serde_json::to_string(self)?;
+        std::fs::write(filename, s).with_context(|| format!("cannot write replay {}", filename.display()))?;
+        Ok(())
+    }
+
+    // finished games are kept here, one file each
+    pub fn user_dir() -> PathBuf {
+        data_dir().join("replays")
+    }
+
+    // save next to earlier replays without overwriting any, named by seed and time, returns where it went
+    pub fn save_new<T: AsRef<Path>>(&self, dir: T) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+        let seed = self.start.seed.unwrap_or(0);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut filename = dir.join(format!("{}-{}.json", seed, now));
+        // the same seed can end twice in one second, restarting a puzzle for one
+        let mut n = 1;
+        while filename.exists() {
+            n += 1;
+            filename = dir.join(format!("{}-{}-{}.json", seed, now, n));
+        }
+        self.save(&filename)?;
+        Ok(filename)
+    }
+
+    pub fn load<T: AsRef<Path>>(filename: T) -> Result<Self> {
+        let filename = filename.as_ref();
+        let s = std::fs::read_to_string(filename)
+            .with_context(|| format!("cannot read replay {}", filename.display()))?;
+        let replay: Replay =
+            serde_json::from_str(&s).with_context(|| format!("cannot parse replay {}", filename.display()))?;
+        replay
+            .start
+            .validate()
+            .with_context(|| format!("replay {} starts from a broken grid", filename.display()))?;
+        Ok(replay)
+    }
+}
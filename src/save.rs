@@ -1,37 +1,252 @@
 use crate::serialization::*;
 use crate::grid::*;
+use crate::mode::GameMode;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use serde_json;
-use std::io::{Write, Read};
-
-use std::fs::OpenOptions;
+use std::fmt;
 use std::fs::File;
-use std::path::Path;
-
-#[allow(unused_must_use)]
-pub fn save<T: AsRef<Path>>(grid: &Grid, filename: T) {
-    let sg = SerializableGrid::from_grid(grid);
-    let s = serde_json::to_string(&sg).unwrap();
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(filename).unwrap();
-    file.write(s.as_bytes());
-    file.flush();
-}
-
-#[allow(unused_must_use)]
-pub fn load<T: AsRef<Path>>(filename: T, default_origin:cgmath::Vector3<i32>) -> Grid {
-    let file = File::open(filename);
-    match file {
-        Ok(mut f) => {
-            let mut s = String::new();
-            f.read_to_string(&mut s);
-            let sg: SerializableGrid = serde_json::from_str(s.as_str()).unwrap();
-            sg.to_grid()
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// what kind of problem a save or load ran into, attached to the error so callers can tell them apart
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SaveError {
+    // there is no save file yet
+    Missing,
+    // the file is there but is not a save this game can read
+    Corrupt,
+    // made by a newer version of the game
+    Incompatible { version: u32, supported: u32 },
+    // reading or writing the file failed
+    Io,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Missing => write!(f, "no saved game"),
+            SaveError::Corrupt => write!(f, "saved game is corrupt"),
+            SaveError::Incompatible { version, supported } => write!(
+                f,
+                "saved game is version {}, newer than this game reads ({})",
+                version, supported
+            ),
+            SaveError::Io => write!(f, "cannot access saved game"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+// the kind of save error behind err, if it came from saving or loading
+pub fn save_error(err: &anyhow::Error) -> Option<SaveError> {
+    err.downcast_ref::<SaveError>().copied()
+}
+
+// how a save is written, load reads either
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SaveFormat {
+    // readable and easy to edit by hand
+    Json,
+    // a fraction of the size, see binary.rs
+    Binary,
+}
+
+pub fn save<T: AsRef<Path>>(grid: &Grid, filename: T) -> Result<()> {
+    save_as(grid, filename, SaveFormat::Json)
+}
+
+pub fn save_as<T: AsRef<Path>>(grid: &Grid, filename: T, format: SaveFormat) -> Result<()> {
+    write_save(&SaveFile::new(grid), filename.as_ref(), format)
+}
+
+// write to a temporary file next to the save and rename it over, so a crash never leaves half a save
+fn write_save(file: &SaveFile, filename: &Path, format: SaveFormat) -> Result<()> {
+    let s = match format {
+        SaveFormat::Json => serde_json::to_vec(&file)?,
+        SaveFormat::Binary => file.to_binary()?,
+    };
+    let mut tmp = filename.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(&s)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, filename)
+    };
+    write()
+        .context(SaveError::Io)
+        .with_context(|| format!("cannot save {}", filename.display()))
+}
+
+pub fn load<T: AsRef<Path>>(filename: T) -> Result<Grid> {
+    let filename = filename.as_ref();
+    let read = || -> Result<Grid> {
+        let bytes = match std::fs::read(filename) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(e).context(SaveError::Missing),
+            Err(e) => return Err(e).context(SaveError::Io),
+        };
+        SaveFile::from_bytes(&bytes)?.grid.to_grid().context(SaveError::Corrupt)
+    };
+    read().with_context(|| format!("cannot load {}", filename.display()))
+}
+
+// per-user directory the game keeps its saves in, following each platform's convention
+pub fn data_dir() -> PathBuf {
+    let home = || std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from).unwrap_or_else(home)
+    } else if cfg!(target_os = "macos") {
+        home().join("Library").join("Application Support")
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home().join(".local").join("share"))
+    };
+    base.join("real3d")
+}
+
+// what a load menu shows about a save without loading the whole grid
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SaveMeta {
+    // seconds since the unix epoch
+    pub saved_at: u64,
+    pub points: u32,
+    pub level: u32,
+    pub mode: GameMode,
+    pub seed: u64,
+    // frames played
+    pub frames: usize,
+}
+
+impl SaveMeta {
+    pub fn new(grid: &Grid) -> Self {
+        Self {
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            points: grid.score.points,
+            level: grid.score.level,
+            mode: grid.mode,
+            seed: grid.seed,
+            frames: grid.frames,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Slot {
+    pub name: String,
+    // why the slot cannot be read if it cannot, it is still listed so it can be deleted or renamed
+    pub meta: std::result::Result<SaveMeta, String>,
+}
+
+// named saves kept as one file each in a directory
+pub struct SaveSlots {
+    pub dir: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // the slots in the user's data directory
+    pub fn user() -> Self {
+        Self::new(data_dir().join("saves"))
+    }
+
+    // slot names become file names, so keep them to characters that are safe everywhere
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let ok = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ';
+        if name.trim().is_empty() || !name.chars().all(ok) {
+            bail!("slot names use letters, numbers, spaces, - and _, not {:?}", name);
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub fn save(&self, name: &str, grid: &Grid) -> Result<Slot> {
+        let path = self.path(name)?;
+        std::fs::create_dir_all(&self.dir)
+            .context(SaveError::Io)
+            .with_context(|| format!("cannot create {}", self.dir.display()))?;
+        // the slot returned carries the same meta as the file, saved_at included
+        let file = SaveFile::new(grid);
+        write_save(&file, &path, SaveFormat::Json)?;
+        Ok(Slot {
+            name: name.to_string(),
+            meta: Ok(file.meta.expect("new saves always carry meta")),
+        })
+    }
+
+    pub fn load(&self, name: &str) -> Result<Grid> {
+        load(self.path(name)?)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        std::fs::remove_file(&path)
+            .map_err(|e| {
+                let kind = if e.kind() == ErrorKind::NotFound { SaveError::Missing } else { SaveError::Io };
+                anyhow::Error::new(e).context(kind)
+            })
+            .with_context(|| format!("cannot delete slot {}", name))
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (old, new) = (self.path(from)?, self.path(to)?);
+        if !old.exists() {
+            return Err(SaveError::Missing).with_context(|| format!("cannot rename slot {}", from));
+        }
+        if new.exists() {
+            bail!("cannot rename slot {}, slot {} already exists", from, to);
+        }
+        std::fs::rename(&old, &new)
+            .context(SaveError::Io)
+            .with_context(|| format!("cannot rename slot {} to {}", from, to))
+    }
+
+    // every slot, most recently saved first, then the ones that cannot be read by name
+    pub fn list(&self) -> Result<Vec<Slot>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).context(SaveError::Io).with_context(|| format!("cannot list {}", self.dir.display())),
+        };
+        let mut slots = vec![];
+        for entry in entries {
+            let path = entry.context(SaveError::Io)?.path();
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) if path.extension().map_or(false, |e| e == "json") => name.to_string(),
+                _ => continue,
+            };
+            let meta = read_meta(&path).map_err(|e| format!("{:#}", e));
+            slots.push(Slot { name, meta });
         }
-        Err(_) => {
-            return Grid::new(default_origin);
+        let saved_at = |s: &Slot| s.meta.as_ref().ok().map(|m| m.saved_at);
+        slots.sort_by(|a, b| saved_at(b).cmp(&saved_at(a)).then_with(|| a.name.cmp(&b.name)));
+        Ok(slots)
+    }
+}
+
+// metadata of a save, read without rebuilding the grid when the save carries it
+fn read_meta(path: &Path) -> Result<SaveMeta> {
+    #[derive(Deserialize)]
+    struct Header {
+        #[serde(default)]
+        meta: Option<SaveMeta>,
+    }
+    let s = std::fs::read_to_string(path).context(SaveError::Io)?;
+    match serde_json::from_str::<Header>(&s) {
+        Ok(Header { meta: Some(meta) }) => Ok(meta),
+        _ => {
+            let mut meta = SaveMeta::new(&load(path)?);
+            // saves made before slots count as saved when the file was last written
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).context(SaveError::Io)?;
+            meta.saved_at = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            Ok(meta)
         }
     }
-}
\ No newline at end of file
+}
+
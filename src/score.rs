@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+// points for clearing 1, 2, 3 and 4 or more planes with one piece, multiplied by the level
+const CLEAR_POINTS: [u32; 4] = [100, 300, 500, 800];
+// bonus per consecutive clearing piece after the first, multiplied by the level
+const COMBO_POINTS: u32 = 50;
+// bonus per cascade step after a piece clears, multiplied by the step and the level
+const CHAIN_POINTS: u32 = 200;
+// points per plane dropped
+const SOFT_DROP_POINTS: u32 = 1;
+const HARD_DROP_POINTS: u32 = 2;
+// garbage planes sent to the opponent for clearing 1, 2, 3 and 4 or more planes at once
+const ATTACK_PLANES: [usize; 4] = [0, 1, 2, 4];
+// planes to clear before the level goes up
+const PLANES_PER_LEVEL: u32 = 10;
+// frames between gravity steps at level 1, and the fastest gravity allowed
+const BASE_GRAVITY: usize = 30;
+const MIN_GRAVITY: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Score {
+    pub points: u32,
+    pub level: u32,
+    pub planes_cleared: u32,
+    pub pieces_placed: u32,
+    // consecutive pieces that cleared at least one plane, 0 if the last piece cleared nothing
+    pub combo: u32,
+    pub max_combo: u32,
+    // number of times 1, 2, 3 and 4 or more planes were cleared at once
+    pub clears: [u32; 4],
+    // most cascades set off by one piece
+    #[serde(default)]
+    pub max_chain: u32,
+    // pieces landed since the last plane was cleared, by the player or for them
+    #[serde(default)]
+    pub since_clear: u32,
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Score {
+    pub fn new() -> Self {
+        Self {
+            points: 0,
+            level: 1,
+            planes_cleared: 0,
+            pieces_placed: 0,
+            combo: 0,
+            max_combo: 0,
+            clears: [0; 4],
+            max_chain: 0,
+            since_clear: 0,
+        }
+    }
+
+    pub fn soft_drop(&mut self, planes: i32) {
+        self.points += planes.max(0) as u32 * SOFT_DROP_POINTS;
+    }
+
+    pub fn hard_drop(&mut self, planes: i32) {
+        self.points += planes.max(0) as u32 * HARD_DROP_POINTS;
+    }
+
+    // record a piece landing and clearing some number of planes
+    pub fn lock_piece(&mut self, planes: usize) {
+        self.pieces_placed += 1;
+        if planes == 0 {
+            self.combo = 0;
+            self.since_clear += 1;
+            return;
+        }
+        self.since_clear = 0;
+        let kind = planes.min(CLEAR_POINTS.len()) - 1;
+        self.clears[kind] += 1;
+        self.points += CLEAR_POINTS[kind] * self.level;
+        if self.combo > 0 {
+            self.points += COMBO_POINTS * self.combo * self.level;
+        }
+        self.combo += 1;
+        self.max_combo = self.max_combo.max(self.combo);
+        self.planes_cleared += planes as u32;
+        self.level = 1 + self.planes_cleared / PLANES_PER_LEVEL;
+    }
+
+    // record the planes each cascade cleared after the piece that started them, in order
+    pub fn chain(&mut self, chains: &[usize]) {
+        for (step, planes) in chains.iter().enumerate() {
+            let kind = (*planes).min(CLEAR_POINTS.len()) - 1;
+            self.clears[kind] += 1;
+            self.points += (CLEAR_POINTS[kind] + CHAIN_POINTS * (step as u32 + 1)) * self.level;
+            self.planes_cleared += *planes as u32;
+            self.level = 1 + self.planes_cleared / PLANES_PER_LEVEL;
+        }
+        self.max_chain = self.max_chain.max(chains.len() as u32);
+    }
+
+    // a plane cleared for the player, which restarts the count of pieces without a clear
+    pub fn assist(&mut self) {
+        self.since_clear = 0;
+    }
+
+    // frames between gravity steps, shorter at higher levels
+    pub fn gravity_interval(&self) -> usize {
+        let faster = (self.level.saturating_sub(1) as usize) * 3;
+        BASE_GRAVITY.saturating_sub(faster).max(MIN_GRAVITY)
+    }
+}
+
+// garbage planes a clear sends in multiplayer, counting each cascade as its own clear
+pub fn attack(planes: usize, chains: &[usize]) -> usize {
+    std::iter::once(&planes)
+        .chain(chains.iter())
+        .filter(|p| **p > 0)
+        .map(|p| ATTACK_PLANES[(*p).min(ATTACK_PLANES.len()) - 1])
+        .sum()
+}
@@ -2,9 +2,50 @@ use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use serde::ser::SerializeTuple;
 use serde::de::DeserializeOwned;
 use crate::grid::*;
+use crate::score::Score;
+use crate::generator::GeneratorState;
+use crate::mode::GameMode;
+use crate::pieces::PieceSet;
+use std::fmt;
+use std::sync::Arc;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use crate::save::{SaveError, SaveMeta};
+use anyhow::{Context, Result};
+use serde_json::Value;
 
 pub type SerializablePos3<T> = [T; 3];
 
+// grids with more cells than this are rejected before anything is allocated for them
+pub const MAX_CELLS: usize = 1 << 20;
+
+// cells in a grid of this size, None if a dimension is below 1 or there are more than MAX_CELLS
+// multiplied in usize and checked, since GridSize::len would overflow i32 on huge sizes
+pub fn checked_cells(size: SerializablePos3<i32>) -> Option<usize> {
+    size.iter()
+        .try_fold(1usize, |n, d| if *d < 1 { None } else { n.checked_mul(*d as usize) })
+        .filter(|n| *n <= MAX_CELLS)
+}
+
+// bumped whenever SerializableGrid changes in a way serde defaults cannot read, with a migration added below
+pub const SAVE_VERSION: u32 = 1;
+
+// MIGRATIONS[v] upgrades a version v grid to version v + 1
+const MIGRATIONS: [fn(&mut Value) -> Result<()>; SAVE_VERSION as usize] = [shape_names_to_indices];
+
+// shape names from before shapes were indices into the piece set, in classic set order
+const SHAPE_NAMES: [&str; 6] = ["Cube", "Line", "T", "Corner", "Z", "L"];
+
+// a grid as it is written to disk, files without a version are bare version 0 grids
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SaveFile {
+    pub version: u32,
+    // missing from saves made before save slots
+    #[serde(default)]
+    pub meta: Option<SaveMeta>,
+    pub grid: SerializableGrid,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SerializableBlock {
     pub color: TetrisColor,
@@ -15,6 +56,15 @@ pub struct SerializableBlock {
 pub struct SerializableTetris {
     pub blocks: Vec<SerializableBlock>,
     pub falling: bool,
+    // missing from saves made before pieces could rotate
+    #[serde(default)]
+    pub shape: Option<TetrisShape>,
+    #[serde(default)]
+    pub pivot: Option<SerializablePos3<f32>>,
+    #[serde(default)]
+    pub lock_timer: usize,
+    #[serde(default)]
+    pub lock_resets: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -22,9 +72,119 @@ pub struct SerializableGrid {
     pub tetris: Vec<SerializableTetris>,
     pub current: usize,
     pub origin: SerializablePos3<i32>,
+    // saves from before grids were resizable are always the default size
+    #[serde(default = "default_size")]
+    pub size: SerializablePos3<i32>,
+    #[serde(default)]
+    pub score: Score,
+    #[serde(default)]
+    pub generator: GeneratorState,
+    #[serde(default)]
+    pub queue: Vec<TetrisShape>,
+    // saves from before seeding get a fresh seed on load
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // how far into the seed's stream the rng has read
+    #[serde(default)]
+    pub rng_pos: u64,
+    #[serde(default)]
+    pub held: Option<TetrisShape>,
+    #[serde(default)]
+    pub hold_used: bool,
+    #[serde(default = "default_lock_delay")]
+    pub lock_delay: usize,
+    #[serde(default = "default_lock_resets")]
+    pub max_lock_resets: usize,
+    #[serde(default)]
+    pub gravity: GravityMode,
+    #[serde(default)]
+    pub mode: GameMode,
+    #[serde(default)]
+    pub frames: usize,
+    #[serde(default)]
+    pub pending_garbage: Vec<Garbage>,
+    // left out for the classic set to keep saves and network updates small
+    #[serde(default = "PieceSet::classic", skip_serializing_if = "PieceSet::is_classic")]
+    pub pieces: Arc<PieceSet>,
     // end is not necessary because ended grid will be serialized as empty on disk
 }
 
+// one thing wrong with a serialized grid
+#[derive(Clone, PartialEq, Debug)]
+pub enum GridProblem {
+    // a size with a dimension below 1 or more than MAX_CELLS cells
+    BadSize(SerializablePos3<i32>),
+    // a block of this tetris lies outside the grid
+    OutOfBounds { tetris: usize, c: SerializablePos3<i32> },
+    // two tetris claim the same cell
+    Overlap { tetris: [usize; 2], c: SerializablePos3<i32> },
+    // current is not one of the tetris
+    BadCurrent { current: usize, len: usize },
+    // only the current tetris can be falling
+    ExtraFalling(Vec<usize>),
+    // a shape that is not in the grid's piece set
+    UnknownShape(TetrisShape),
+    // the piece set itself is broken, with what is wrong with it
+    BadPieces(String),
+    // a piece that cannot spawn because it is wider, taller or deeper than the grid
+    PieceTooBig(String),
+    // pending garbage with more planes than the grid is tall, by its place in the list
+    GarbageTooTall { garbage: usize, planes: usize },
+}
+
+impl fmt::Display for GridProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GridProblem::BadSize(s) => write!(
+                f,
+                "size {:?} is not at least 1 in every direction and at most {} cells",
+                s, MAX_CELLS
+            ),
+            GridProblem::OutOfBounds { tetris, c } => write!(f, "tetris {} has a block at {:?} outside the grid", tetris, c),
+            GridProblem::Overlap { tetris, c } => write!(f, "tetris {} and {} both have a block at {:?}", tetris[0], tetris[1], c),
+            GridProblem::BadCurrent { current, len } => write!(f, "current tetris {} is not one of the {} tetris", current, len),
+            GridProblem::ExtraFalling(tetris) => write!(f, "tetris {:?} are falling but are not the current one", tetris),
+            GridProblem::UnknownShape(shape) => write!(f, "shape {} is not in the piece set", shape),
+            GridProblem::BadPieces(e) => write!(f, "piece set is broken: {}", e),
+            GridProblem::PieceTooBig(name) => write!(f, "piece {} does not fit in the grid", name),
+            GridProblem::GarbageTooTall { garbage, planes } => {
+                write!(f, "pending garbage {} has {} planes, more than the grid is tall", garbage, planes)
+            }
+        }
+    }
+}
+
+// every problem found in a serialized grid
+#[derive(Clone, PartialEq, Debug)]
+pub struct InvalidGrid {
+    pub problems: Vec<GridProblem>,
+}
+
+impl fmt::Display for InvalidGrid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid grid:")?;
+        for p in self.problems.iter() {
+            write!(f, "\n  {}", p)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidGrid {}
+
+fn default_size() -> SerializablePos3<i32> {
+    let s = GridSize::default();
+    [s.x, s.y, s.z]
+}
+
+fn default_lock_delay() -> usize {
+    DEFAULT_LOCK_DELAY
+}
+
+fn default_lock_resets() -> usize {
+    DEFAULT_LOCK_RESETS
+}
+
 impl SerializableBlock {
     pub fn from_block(b: &Block) -> Self {
         Self {
@@ -46,13 +206,32 @@ impl SerializableTetris {
         Self {
             falling: t.falling,
             blocks: t.blocks.iter().map(|b| SerializableBlock::from_block(b)).collect(),
+            shape: Some(t.shape),
+            pivot: Some([t.pivot.x, t.pivot.y, t.pivot.z]),
+            lock_timer: t.lock_timer,
+            lock_resets: t.lock_resets,
         }
     }
 
-    pub fn to_tetris(&self) -> Tetris {
+    pub fn to_tetris(&self, pieces: &PieceSet) -> Tetris {
+        // old saves rotate around their first block
+        let pivot = match (self.pivot, self.blocks.first()) {
+            (Some(p), _) => cgmath::Point3::new(p[0], p[1], p[2]),
+            (None, Some(b)) => cgmath::Point3::new(b.c[0] as f32, b.c[1] as f32, b.c[2] as f32),
+            (None, None) => cgmath::Point3::new(0.0, 0.0, 0.0),
+        };
+        let shape = match (self.shape, self.blocks.first()) {
+            (Some(s), _) => s,
+            (None, Some(b)) => pieces.shape_from_color(b.color),
+            (None, None) => 0,
+        };
         Tetris {
             falling: self.falling,
             blocks: self.blocks.iter().map(|b| b.to_block()).collect(),
+            shape,
+            pivot,
+            lock_timer: self.lock_timer,
+            lock_resets: self.lock_resets,
         }
     }
 }
@@ -62,29 +241,238 @@ impl SerializableGrid {
         Self{
             current:g.current,
             origin:[g.origin.x,g.origin.y,g.origin.z],
+            size:[g.size.x,g.size.y,g.size.z],
+            score:g.score,
+            generator:g.generator.state(),
+            queue:g.queue.iter().cloned().collect(),
+            seed:Some(g.seed),
+            rng_pos:g.rng.get_word_pos() as u64,
+            held:g.held,
+            hold_used:g.hold_used,
+            lock_delay:g.lock_delay,
+            max_lock_resets:g.max_lock_resets,
+            gravity:g.gravity,
+            mode:g.mode,
+            frames:g.frames,
+            pending_garbage:g.pending_garbage.clone(),
+            pieces: g.pieces.clone(),
             tetris:g.tetris.iter().map(|t| SerializableTetris::from_tetris(t)).collect()
         }
     }
 
-    pub fn to_grid(&self)->Grid{
-        let mut grid=[GridBlock::Vacant; (GRID_X_MAX * GRID_Y_MAX * GRID_Z_MAX) as usize];
+    // check the grid can be rebuilt without indexing out of range or two pieces sharing a cell
+    pub fn validate(&self) -> std::result::Result<(), InvalidGrid> {
+        let mut problems = vec![];
+        let size_ok = checked_cells(self.size).is_some();
+        if !size_ok {
+            problems.push(GridProblem::BadSize(self.size));
+        } else {
+            let size = GridSize::new(self.size[0], self.size[1], self.size[2]);
+            let mut owner: Vec<Option<usize>> = vec![None; size.len()];
+            for (i, t) in self.tetris.iter().enumerate() {
+                for b in t.blocks.iter() {
+                    let c = GridCoord::new(b.c[0], b.c[1], b.c[2]);
+                    if !size.contains(c) {
+                        problems.push(GridProblem::OutOfBounds { tetris: i, c: b.c });
+                        continue;
+                    }
+                    let k = size.coord_to_index(c);
+                    match owner[k] {
+                        // a tetris listing the same cell twice is broken too
+                        Some(j) => problems.push(GridProblem::Overlap { tetris: [j, i], c: b.c }),
+                        None => owner[k] = Some(i),
+                    }
+                }
+            }
+        }
+        if self.current >= self.tetris.len() {
+            problems.push(GridProblem::BadCurrent {
+                current: self.current,
+                len: self.tetris.len(),
+            });
+        }
+        let extra: Vec<usize> = (0..self.tetris.len())
+            .filter(|i| self.tetris[*i].falling && *i != self.current)
+            .collect();
+        if !extra.is_empty() {
+            problems.push(GridProblem::ExtraFalling(extra));
+        }
+        if let Err(e) = self.pieces.validate() {
+            problems.push(GridProblem::BadPieces(e.to_string().replace('\n', "; ")));
+        }
+        if size_ok {
+            let size = GridSize::new(self.size[0], self.size[1], self.size[2]);
+            for p in self.pieces.pieces.iter().filter(|p| !p.fits(size)) {
+                problems.push(GridProblem::PieceTooBig(p.name.clone()));
+            }
+            for (i, g) in self.pending_garbage.iter().enumerate() {
+                if g.planes > self.size[1] as usize {
+                    problems.push(GridProblem::GarbageTooTall { garbage: i, planes: g.planes });
+                }
+            }
+        }
+        let dealt: &[TetrisShape] = match &self.generator {
+            GeneratorState::Uniform => &[],
+            GeneratorState::Bag { remaining } => remaining,
+            GeneratorState::Sequence { shapes, .. } => shapes,
+        };
+        let shapes = self
+            .tetris
+            .iter()
+            .filter_map(|t| t.shape)
+            .chain(self.queue.iter().copied())
+            .chain(self.held)
+            .chain(dealt.iter().copied());
+        for shape in shapes {
+            let problem = GridProblem::UnknownShape(shape);
+            if shape >= self.pieces.pieces.len() && !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidGrid { problems })
+        }
+    }
+
+    pub fn to_grid(&self)->Result<Grid>{
+        self.validate()?;
+        let size=GridSize::new(self.size[0],self.size[1],self.size[2]);
+        let mut grid=vec![GridBlock::Vacant; size.len()];
         let mut tetris:Vec<Tetris>=vec![];
         for (i,st) in self.tetris.iter().enumerate(){
-            let t=st.to_tetris();
+            let t=st.to_tetris(&self.pieces);
             for b in t.blocks.iter(){
-                grid[Grid::coord_to_index(b.c)]=GridBlock::Occupied(i);
+                grid[size.coord_to_index(b.c)]=GridBlock::Occupied(i);
             }
             tetris.push(t);
         }
 
-        Grid{
+        let seed=self.seed.unwrap_or_else(random_seed);
+        let mut rng=ChaCha8Rng::seed_from_u64(seed);
+        rng.set_word_pos(self.rng_pos as u128);
+        let mut g=Grid{
             tetris,
             current: self.current,
             origin: cgmath::Vector3::<i32>::new(self.origin[0],self.origin[1],self.origin[2]),
             end: false,
-            grid
-        }
+            size,
+            pieces: self.pieces.clone(),
+            grid,
+            score: self.score,
+            generator: self.generator.to_generator(),
+            queue: self.queue.iter().cloned().collect(),
+            seed,
+            rng,
+            held: self.held,
+            hold_used: self.hold_used,
+            lock_delay: self.lock_delay,
+            max_lock_resets: self.max_lock_resets,
+            gravity: self.gravity,
+            mode: self.mode,
+            frames: self.frames,
+            won: false,
+            pending_garbage: self.pending_garbage.clone(),
+            history: None,
+        };
+        g.fill_queue();
+        Ok(g)
     }
 }
 
+impl SaveFile {
+    pub fn new(grid: &Grid) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            meta: Some(SaveMeta::new(grid)),
+            grid: SerializableGrid::from_grid(grid),
+        }
+    }
+
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        crate::binary::encode_save(self)
+    }
 
+    // read a save in either format, telling them apart by the binary magic
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(crate::binary::MAGIC) {
+            return crate::binary::decode_save(bytes);
+        }
+        let s = std::str::from_utf8(bytes).context(SaveError::Corrupt)?;
+        Self::from_json(s)
+    }
+
+    // read a save of any version, migrating it up to the current one
+    pub fn from_json(s: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(s).context(SaveError::Corrupt)?;
+        let version = match value.get("version") {
+            Some(v) => v.as_u64().context("save version is not a number").context(SaveError::Corrupt)? as u32,
+            None => 0,
+        };
+        if version > SAVE_VERSION {
+            return Err(SaveError::Incompatible {
+                version,
+                supported: SAVE_VERSION,
+            }
+            .into());
+        }
+        Self::migrate(value, version).context(SaveError::Corrupt)
+    }
+
+    fn migrate(mut value: Value, version: u32) -> Result<Self> {
+        let (meta, mut grid) = if version == 0 {
+            (None, value)
+        } else {
+            let meta = match value.get_mut("meta").map(Value::take) {
+                Some(Value::Null) | None => None,
+                Some(meta) => Some(serde_json::from_value(meta).context("save metadata is broken")?),
+            };
+            (meta, value.get_mut("grid").context("save has no grid")?.take())
+        };
+        for migrate in MIGRATIONS[version as usize..].iter() {
+            migrate(&mut grid)?;
+        }
+        let grid = serde_json::from_value(grid).context("save does not match the grid format")?;
+        Ok(Self {
+            version: SAVE_VERSION,
+            meta,
+            grid,
+        })
+    }
+}
+
+// 0 to 1: shapes were saved by name before they became piece set indices, saves made since are left alone
+fn shape_names_to_indices(grid: &mut Value) -> Result<()> {
+    fn index(shape: &mut Value) -> Result<()> {
+        if let Value::String(name) = shape {
+            let i = SHAPE_NAMES
+                .iter()
+                .position(|n| n == name)
+                .with_context(|| format!("unknown shape {} in save", name))?;
+            *shape = Value::from(i);
+        }
+        Ok(())
+    }
+    if let Some(tetris) = grid.get_mut("tetris").and_then(Value::as_array_mut) {
+        for t in tetris {
+            if let Some(shape) = t.get_mut("shape") {
+                index(shape)?;
+            }
+        }
+    }
+    if let Some(queue) = grid.get_mut("queue").and_then(Value::as_array_mut) {
+        for shape in queue {
+            index(shape)?;
+        }
+    }
+    if let Some(held) = grid.get_mut("held") {
+        index(held)?;
+    }
+    if let Some(remaining) = grid.pointer_mut("/generator/Bag/remaining").and_then(Value::as_array_mut) {
+        for shape in remaining {
+            index(shape)?;
+        }
+    }
+    Ok(())
+}
@@ -5,12 +5,16 @@ MAX_PLAYERS = 2
 class EstablishedConnection:
     cid = 0
     instances = set()
+    # guards garbage, which other connections' threads append to
+    lock = threading.Lock()
 
     def __init__(self, conn: socket.socket, addr):
         self.conn = conn
         self.addr = addr
         self.id = self.cid
         self.data = None
+        # attacks waiting to be sent to this player
+        self.garbage = []
         if len(EstablishedConnection.instances) == MAX_PLAYERS:
             self.conn.send(b'-1\n')
             self.stop()
@@ -45,10 +49,14 @@ class EstablishedConnection:
                     break
                 elif op == 'update':
                     self.data = obj['data']
-                    resp = []
-                    for o in filter(lambda i: i != self, self.instances):
-                        resp.append(o.data)
-                    # resp.sort(key=lambda o:o['id'])
+                    grids = []
+                    with EstablishedConnection.lock:
+                        for o in filter(lambda i: i != self, self.instances):
+                            grids.append(o.data)
+                            o.garbage.extend(obj.get('garbage', []))
+                        garbage, self.garbage = self.garbage, []
+                    # grids.sort(key=lambda o:o['id'])
+                    resp = {'grids': grids, 'garbage': garbage}
                     self.conn.send((json.dumps(resp) + '\n').encode('ascii'))
             except (OSError, ConnectionResetError):
                 self.instances.remove(self)
@@ -0,0 +1,162 @@
+use crate::grid::*;
+use crate::score::attack;
+use crate::serialization::SerializableGrid;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// one player action, what a key press does in the demo
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Input {
+    // moves in the xz plane, in move_xz order
+    Left,
+    Right,
+    Up,
+    Down,
+    Rotate(Axis, RotateDir),
+    SoftDrop,
+    HardDrop,
+    Hold,
+    // practice mode only
+    Undo,
+    Redo,
+    // an attack from the opponent arriving, an input so replays of multiplayer games get it on the same frame
+    Garbage(Garbage),
+}
+
+// what happened during one step, so the caller can play sounds and print messages
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct StepEvents {
+    // planes cleared by the piece that landed this step
+    pub planes: usize,
+    // planes each cascade cleared after that
+    pub chains: Vec<usize>,
+    // the game mode cleared a plane for the player
+    pub assisted: bool,
+    // a new piece spawned
+    pub spawned: bool,
+    // garbage planes to send to the opponent, after cancelling any pending against us
+    pub attack: usize,
+}
+
+// the game rules without a window, stepped one frame at a time
+pub struct Simulation {
+    pub grid: Grid,
+    // inputs for each upcoming frame, front first
+    pub script: VecDeque<Vec<Input>>,
+    // frames stepped so far
+    pub frame: usize,
+}
+
+impl Simulation {
+    pub fn new(grid: Grid) -> Self {
+        Self {
+            grid,
+            script: VecDeque::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn with_script(grid: Grid, script: Vec<Vec<Input>>) -> Self {
+        Self {
+            grid,
+            script: script.into(),
+            frame: 0,
+        }
+    }
+
+    // step one frame with the next scripted inputs, or none once the script runs out
+    pub fn step(&mut self) -> StepEvents {
+        let inputs = self.script.pop_front().unwrap_or_default();
+        self.step_with(&inputs)
+    }
+
+    // step until the script is used up or the game ends, returns the frames stepped
+    pub fn run_script(&mut self) -> usize {
+        let start = self.frame;
+        while !self.script.is_empty() && !self.grid.end {
+            self.step();
+        }
+        self.frame - start
+    }
+
+    // step one frame, landing and clearing first, then gravity and locking, then the inputs in order
+    pub fn step_with(&mut self, inputs: &[Input]) -> StepEvents {
+        let mut events = StepEvents::default();
+        let grid = &mut self.grid;
+
+        // when current piece lands, check to clear plane and spawn new piece
+        if !grid.tetris[grid.current].falling && !grid.end {
+            let planes = grid.check_planes();
+            grid.score.lock_piece(planes.len());
+            if !planes.is_empty() {
+                events.planes = planes.len();
+                events.chains = grid.clear_planes(&planes);
+                grid.score.chain(&events.chains);
+                events.attack = grid.cancel_garbage(attack(events.planes, &events.chains));
+            } else {
+                if let Some(y) = grid.mode.assist_plane(&grid.score) {
+                    grid.clear_plane(y);
+                    grid.score.assist();
+                    events.assisted = true;
+                }
+                // garbage only comes in when a piece lands without clearing
+                grid.insert_pending_garbage();
+            }
+            // spawn new piece unless that finished the game
+            grid.check_goal();
+            if !grid.end {
+                grid.add_tetris();
+                grid.record_history();
+                events.spawned = true;
+            }
+        }
+        grid.tick();
+
+        let curr = grid.current;
+        if grid.tetris[curr].falling && self.frame % grid.score.gravity_interval() == 0 {
+            grid.lower_tetris(curr);
+        }
+        // lock the piece once it has rested long enough, the next step spawns a new one
+        grid.update_lock(curr);
+
+        for input in inputs {
+            // undo and redo swap the whole grid, so look the piece up again each time
+            let curr = grid.current;
+            match *input {
+                Input::Left => grid.move_xz(curr, 0),
+                Input::Right => grid.move_xz(curr, 1),
+                Input::Up => grid.move_xz(curr, 2),
+                Input::Down => grid.move_xz(curr, 3),
+                Input::Rotate(axis, dir) => {
+                    grid.rotate_tetris(curr, axis, dir);
+                }
+                Input::SoftDrop => {
+                    if grid.tetris[curr].falling && grid.lower_tetris(curr) {
+                        grid.score.soft_drop(1);
+                    }
+                }
+                Input::HardDrop => {
+                    let d = grid.drop_tetris(curr);
+                    grid.score.hard_drop(d);
+                }
+                Input::Hold => {
+                    grid.hold_current();
+                }
+                Input::Undo => {
+                    grid.undo();
+                }
+                Input::Redo => {
+                    grid.redo();
+                }
+                Input::Garbage(garbage) => grid.pending_garbage.push(garbage),
+            }
+        }
+        self.frame += 1;
+        events
+    }
+
+    // copy of the whole game state, the same form used for saves and the network
+    pub fn snapshot(&self) -> SerializableGrid {
+        SerializableGrid::from_grid(&self.grid)
+    }
+}
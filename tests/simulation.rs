@@ -0,0 +1,90 @@
+use real3d::grid::*;
+use real3d::sim::*;
+
+fn grid(seed: u64) -> Grid {
+    Grid::new(cgmath::Vector3::new(-4, 1, -3), GridSize::default(), seed)
+}
+
+// every landed block, sorted so the order pieces landed in does not matter
+fn landed(sim: &Simulation) -> Vec<[i32; 3]> {
+    let mut cells: Vec<_> = sim
+        .snapshot()
+        .tetris
+        .iter()
+        .filter(|t| !t.falling)
+        .flat_map(|t| t.blocks.iter().map(|b| b.c))
+        .collect();
+    cells.sort();
+    cells
+}
+
+fn script() -> Vec<Vec<Input>> {
+    vec![
+        vec![Input::Left, Input::Left],
+        vec![Input::HardDrop],
+        vec![],
+        vec![Input::Right, Input::Right, Input::Up],
+        vec![Input::Rotate(Axis::Y, RotateDir::Clockwise)],
+        vec![Input::HardDrop],
+        vec![],
+        vec![Input::Down],
+        vec![Input::HardDrop],
+        vec![],
+    ]
+}
+
+#[test]
+fn seeded_script_is_deterministic() {
+    let mut sim = Simulation::with_script(grid(42), script());
+    assert_eq!(sim.run_script(), 10);
+    assert_eq!(
+        landed(&sim),
+        vec![
+            [2, 0, 4],
+            [2, 1, 4],
+            [2, 2, 4],
+            [2, 3, 4],
+            [4, 0, 3],
+            [4, 1, 3],
+            [5, 0, 5],
+            [5, 1, 3],
+            [5, 2, 3],
+            [6, 0, 4],
+            [6, 0, 5],
+            [6, 1, 5],
+        ]
+    );
+    let snapshot = sim.snapshot();
+    assert_eq!(snapshot.queue, vec![2, 0, 4]);
+    assert_eq!(snapshot.tetris[snapshot.current].shape, Some(5));
+    assert_eq!(snapshot.score.pieces_placed, 3);
+    assert_eq!(snapshot.score.points, 76);
+    assert_eq!(snapshot.frames, 10);
+
+    // the same seed and script give the same game down to the last field
+    let mut again = Simulation::with_script(grid(42), script());
+    again.run_script();
+    assert_eq!(
+        serde_json::to_string(&again.snapshot()).unwrap(),
+        serde_json::to_string(&snapshot).unwrap()
+    );
+}
+
+#[test]
+fn topping_out_ends_the_game() {
+    // dropping every piece straight down stacks them in the middle until one cannot spawn
+    let mut sim = Simulation::with_script(grid(7), vec![vec![Input::HardDrop]; 2000]);
+    let frames = sim.run_script();
+    assert!(sim.grid.end);
+    assert!(frames < 2000);
+    assert!(sim.grid.score.pieces_placed > 0);
+
+    // stepping an ended game leaves the stack alone
+    let stack = landed(&sim);
+    for _ in 0..10 {
+        let events = sim.step_with(&[Input::HardDrop]);
+        assert!(!events.spawned);
+    }
+    assert!(sim.grid.end);
+    assert_eq!(landed(&sim), stack);
+}